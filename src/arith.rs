@@ -0,0 +1,351 @@
+//! Arithmetic expressions, as in `for ((i = 0; i < n; i++))`.
+//!
+//! Values are 64-bit signed integers that wrap on overflow, and the operators
+//! are those of C, with `**` for exponentiation. A name stands for the value
+//! of that variable, or 0 if it is unset or empty.
+use crate::exec::Shell;
+use crate::lexer::is_name;
+
+use std::io;
+
+/// Operators, longest first so that `<<=` isn't read as `<<` then `=`.
+const OPERATORS: [&str; 39] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*=", "/=",
+    "%=", "+=", "-=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "^",
+    "|", "?", ":", ",", "(", ")",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+#[derive(Debug)]
+enum Expr {
+    Number(i64),
+    Variable(String),
+    /// `!x`, `~x`, `-x` or `+x`
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    /// `name = x`, or `name op= x` with the operator
+    Assign(String, Option<&'static str>, Box<Expr>),
+    /// `++name` or `--name` when `prefix` is set, otherwise `name++` or `name--`
+    Increment {
+        name: String,
+        delta: i64,
+        prefix: bool,
+    },
+    /// `condition ? then : otherwise`
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+impl Shell {
+    /// Evaluates the arithmetic expression `text`, whose parameters have
+    /// already been expanded. An empty expression is 0.
+    pub fn evaluate(&mut self, text: &str) -> io::Result<i64> {
+        let error = |msg: &str| io::Error::other(format!("{}: {msg}", text.trim()));
+        let tokens = tokenize(text).map_err(|msg| error(&msg))?;
+        if tokens.is_empty() {
+            return Ok(0);
+        }
+
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.comma().map_err(|msg| error(&msg))?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(error(&format!("syntax error in expression near {token:?}")));
+        }
+        self.eval(&expr).map_err(|msg| error(&msg))
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<i64, String> {
+        Ok(match expr {
+            Expr::Number(n) => *n,
+            Expr::Variable(name) => self.arith_variable(name)?,
+            Expr::Unary(op, x) => {
+                let x = self.eval(x)?;
+                match *op {
+                    "!" => (x == 0) as i64,
+                    "~" => !x,
+                    "-" => x.wrapping_neg(),
+                    _ => x,
+                }
+            }
+            // Only evaluated as far as needed to know the result
+            Expr::Binary("&&", a, b) => (self.eval(a)? != 0 && self.eval(b)? != 0) as i64,
+            Expr::Binary("||", a, b) => (self.eval(a)? != 0 || self.eval(b)? != 0) as i64,
+            Expr::Binary(",", a, b) => {
+                self.eval(a)?;
+                self.eval(b)?
+            }
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (self.eval(a)?, self.eval(b)?);
+                apply(op, a, b)?
+            }
+            Expr::Assign(name, op, x) => {
+                let x = self.eval(x)?;
+                let value = match op {
+                    Some(op) => apply(op, self.arith_variable(name)?, x)?,
+                    None => x,
+                };
+                self.env.set(name, value.to_string());
+                value
+            }
+            Expr::Increment {
+                name,
+                delta,
+                prefix,
+            } => {
+                let old = self.arith_variable(name)?;
+                let new = old.wrapping_add(*delta);
+                self.env.set(name, new.to_string());
+                if *prefix {
+                    new
+                } else {
+                    old
+                }
+            }
+            Expr::Conditional(condition, then, otherwise) => match self.eval(condition)? {
+                0 => self.eval(otherwise)?,
+                _ => self.eval(then)?,
+            },
+        })
+    }
+
+    /// The value of a variable in an expression.
+    fn arith_variable(&self, name: &str) -> Result<i64, String> {
+        let value = self.env.get(name).unwrap_or_default().trim();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        match tokenize(value)?.as_slice() {
+            [Token::Number(n)] => Ok(*n),
+            [Token::Op("-"), Token::Number(n)] => Ok(n.wrapping_neg()),
+            _ => Err(format!("{name}: {value}: not a number")),
+        }
+    }
+}
+
+/// Applies a binary operator other than `&&`, `||` and `,`.
+fn apply(op: &str, a: i64, b: i64) -> Result<i64, String> {
+    Ok(match op {
+        "+" => a.wrapping_add(b),
+        "-" => a.wrapping_sub(b),
+        "*" => a.wrapping_mul(b),
+        "/" | "%" if b == 0 => return Err("division by 0".into()),
+        "/" => a.wrapping_div(b),
+        "%" => a.wrapping_rem(b),
+        "**" if b < 0 => return Err("exponent less than 0".into()),
+        "**" => a.wrapping_pow(b.try_into().unwrap_or(u32::MAX)),
+        "<<" => a.wrapping_shl(b as u32),
+        ">>" => a.wrapping_shr(b as u32),
+        "<" => (a < b) as i64,
+        ">" => (a > b) as i64,
+        "<=" => (a <= b) as i64,
+        ">=" => (a >= b) as i64,
+        "==" => (a == b) as i64,
+        "!=" => (a != b) as i64,
+        "&" => a & b,
+        "^" => a ^ b,
+        "|" => a | b,
+        _ => unreachable!("{op} is not a binary operator"),
+    })
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(number(&rest[..len])?));
+            len
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_string()));
+            len
+        } else {
+            let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) else {
+                return Err(format!("syntax error: invalid arithmetic operator `{c}'"));
+            };
+            tokens.push(Token::Op(op));
+            op.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Parses a decimal number, or a hexadecimal one starting with `0x` or an
+/// octal one starting with `0`.
+fn number(text: &str) -> Result<i64, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None if text.len() > 1 && text.starts_with('0') => i64::from_str_radix(&text[1..], 8),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("{text}: invalid number"))
+}
+
+/// The precedence of a binary operator, higher binding tighter.
+fn precedence(op: &str) -> Option<u8> {
+    Some(match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | ">" | "<=" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        "**" => 11,
+        _ => return None,
+    })
+}
+
+/// A recursive descent parser, from the loosest binding operator to the
+/// tightest.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next_op(&mut self, op: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Op(o)) if *o == op);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    /// `a, b`, evaluating both and giving `b`.
+    fn comma(&mut self) -> Result<Expr, String> {
+        let mut expr = self.assign()?;
+        while self.next_op(",") {
+            expr = Expr::Binary(",", Box::new(expr), Box::new(self.assign()?));
+        }
+        Ok(expr)
+    }
+
+    fn assign(&mut self) -> Result<Expr, String> {
+        if let [Token::Name(name), Token::Op(op), ..] = &self.tokens[self.pos..] {
+            let op = match *op {
+                "=" => Some(None),
+                "*=" | "/=" | "%=" | "+=" | "-=" | "<<=" | ">>=" | "&=" | "^=" | "|=" => {
+                    Some(op.strip_suffix('='))
+                }
+                _ => None,
+            };
+            if let Some(op) = op {
+                let name = name.clone();
+                self.pos += 2;
+                return Ok(Expr::Assign(name, op, Box::new(self.assign()?)));
+            }
+        }
+        self.conditional()
+    }
+
+    fn conditional(&mut self) -> Result<Expr, String> {
+        let condition = self.binary(1)?;
+        if !self.next_op("?") {
+            return Ok(condition);
+        }
+        let then = self.comma()?;
+        if !self.next_op(":") {
+            return Err("`:' expected for conditional expression".into());
+        }
+        let otherwise = self.assign()?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    /// Operators binding at least as tightly as `min`.
+    fn binary(&mut self, min: u8) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while let Some(&Token::Op(op)) = self.peek() {
+            let Some(prec) = precedence(op).filter(|prec| *prec >= min) else {
+                break;
+            };
+            self.pos += 1;
+            // `**` groups to the right, everything else to the left
+            let right = match op {
+                "**" => self.binary(prec)?,
+                _ => self.binary(prec + 1)?,
+            };
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        for op in ["++", "--"] {
+            if let [Token::Op(o), Token::Name(name), ..] = &self.tokens[self.pos..] {
+                if *o == op {
+                    let name = name.clone();
+                    self.pos += 2;
+                    return Ok(Expr::Increment {
+                        name,
+                        delta: if op == "++" { 1 } else { -1 },
+                        prefix: true,
+                    });
+                }
+            }
+        }
+        for op in ["!", "~", "-", "+"] {
+            if self.next_op(op) {
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Expr, String> {
+        let expr = self.primary()?;
+        let Expr::Variable(name) = &expr else {
+            return Ok(expr);
+        };
+        for (op, delta) in [("++", 1), ("--", -1)] {
+            if self.next_op(op) {
+                return Ok(Expr::Increment {
+                    name: name.clone(),
+                    delta,
+                    prefix: false,
+                });
+            }
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Name(name)) if is_name(&name) => Ok(Expr::Variable(name)),
+            Some(Token::Op("(")) => {
+                let expr = self.comma()?;
+                match self.next_op(")") {
+                    true => Ok(expr),
+                    false => Err("missing `)'".into()),
+                }
+            }
+            Some(token) => Err(format!("syntax error: operand expected near {token:?}")),
+            None => Err("syntax error: operand expected".into()),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Brace expansion, the first expansion applied to a word.
+//!
+//! `{a,b,c}` makes a copy of the word for each of the comma-separated
+//! strings, and `{x..y[..step]}` one for each number or character in the
+//! range. Only unquoted braces expand, and the braces of `${...}` never do.
+use crate::parser::Tree;
+use crate::span::Spanned;
+
+/// A character of the word that braces can apply to, or a part of it that
+/// they can't see into.
+#[derive(Clone)]
+enum Item<'a> {
+    /// A character, and whether it came from a pattern
+    Char(char, bool),
+    Part(&'a Tree<Spanned>),
+}
+
+/// The words that `word` expands to, in order.
+pub fn expand(word: &Tree<Spanned>) -> Vec<Tree<Spanned>> {
+    let parts = match word {
+        Tree::Word(parts) => parts.as_slice(),
+        _ => std::slice::from_ref(word),
+    };
+    let has_brace = parts
+        .iter()
+        .any(|part| matches!(part, Tree::Literal(s) | Tree::Glob(s) if s.contains('{')));
+    if !has_brace {
+        return vec![word.clone()];
+    }
+
+    let items: Vec<Item> = parts
+        .iter()
+        .flat_map(|part| match part {
+            Tree::Literal(lit) => lit.chars().map(|c| Item::Char(c, false)).collect(),
+            Tree::Glob(pattern) => pattern.chars().map(|c| Item::Char(c, true)).collect(),
+            part => vec![Item::Part(part)],
+        })
+        .collect();
+    expand_items(&items)
+        .iter()
+        .map(|items| to_word(items))
+        .collect()
+}
+
+fn expand_items<'a>(items: &[Item<'a>]) -> Vec<Vec<Item<'a>>> {
+    for (open, item) in items.iter().enumerate() {
+        if !matches!(item, Item::Char('{', _)) {
+            continue;
+        }
+        let Some((close, commas)) = find_close(&items[open + 1..]) else {
+            continue;
+        };
+        let close = open + 1 + close;
+        let (prefix, inner, suffix) =
+            (&items[..open], &items[open + 1..close], &items[close + 1..]);
+
+        let alternatives: Vec<Vec<Item>> = if !commas.is_empty() {
+            let mut start = 0;
+            commas
+                .iter()
+                .chain([&inner.len()])
+                .map(|&comma| {
+                    let alternative = inner[start..comma].to_vec();
+                    start = comma + 1;
+                    alternative
+                })
+                .collect()
+        } else if let Some(range) = range(inner) {
+            range
+                .into_iter()
+                .map(|s| s.chars().map(|c| Item::Char(c, false)).collect())
+                .collect()
+        } else {
+            // Braces without a comma or a range are just braces
+            continue;
+        };
+
+        let mut words = vec![];
+        for alternative in alternatives {
+            let word: Vec<Item> = prefix
+                .iter()
+                .chain(&alternative)
+                .chain(suffix)
+                .cloned()
+                .collect();
+            words.extend(expand_items(&word));
+        }
+        return words;
+    }
+    vec![items.to_vec()]
+}
+
+/// Finds the `}` closing a brace whose contents start `items`, along with
+/// the positions of the commas that aren't inside nested braces.
+fn find_close(items: &[Item]) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = vec![];
+    for (i, item) in items.iter().enumerate() {
+        match item {
+            Item::Char('{', _) => depth += 1,
+            Item::Char('}', _) if depth == 0 => return Some((i, commas)),
+            Item::Char('}', _) => depth -= 1,
+            Item::Char(',', _) if depth == 0 => commas.push(i),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// The strings of a range such as `1..10`, `a..e` or `00..20..5`.
+fn range(items: &[Item]) -> Option<Vec<String>> {
+    let text = items
+        .iter()
+        .map(|item| match item {
+            Item::Char(c, _) => Some(*c),
+            Item::Part(_) => None,
+        })
+        .collect::<Option<String>>()?;
+
+    let mut bounds = text.split("..");
+    let (start, end) = (bounds.next()?, bounds.next()?);
+    let step: i64 = match bounds.next() {
+        Some(step) => step.parse().ok()?,
+        None => 1,
+    };
+    if bounds.next().is_some() {
+        return None;
+    }
+    // The direction comes from the bounds, not the sign of the step
+    let step = match step.unsigned_abs() {
+        0 => 1,
+        step => step as usize,
+    };
+
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        // A leading zero pads every number to the width of the widest bound
+        let padded = |s: &str| {
+            s.trim_start_matches('-').len() > 1 && s.trim_start_matches('-').starts_with('0')
+        };
+        let width = match padded(start) || padded(end) {
+            true => start.len().max(end.len()),
+            false => 0,
+        };
+        let numbers: Vec<i64> = match first <= last {
+            true => (first..=last).step_by(step).collect(),
+            false => (last..=first).rev().step_by(step).collect(),
+        };
+        return Some(
+            numbers
+                .into_iter()
+                .map(|n| match n < 0 {
+                    true => format!("-{:0width$}", -n, width = width.saturating_sub(1)),
+                    false => format!("{n:0width$}"),
+                })
+                .collect(),
+        );
+    }
+
+    let (mut first, mut last) = (start.chars(), end.chars());
+    let (Some(first), None, Some(last), None) =
+        (first.next(), first.next(), last.next(), last.next())
+    else {
+        return None;
+    };
+    let chars: Vec<char> = match first <= last {
+        true => (first..=last).step_by(step).collect(),
+        false => (last..=first).rev().step_by(step).collect(),
+    };
+    Some(chars.into_iter().map(String::from).collect())
+}
+
+/// Turns expanded items back into a word.
+fn to_word(items: &[Item]) -> Tree<Spanned> {
+    let mut parts: Vec<Tree<Spanned>> = vec![];
+    for item in items {
+        match (parts.last_mut(), item) {
+            (Some(Tree::Literal(s)), Item::Char(c, false))
+            | (Some(Tree::Glob(s)), Item::Char(c, true)) => s.push(*c),
+            (_, Item::Char(c, false)) => parts.push(Tree::Literal(c.to_string().into())),
+            (_, Item::Char(c, true)) => parts.push(Tree::Glob(c.to_string().into())),
+            (_, Item::Part(part)) => parts.push((*part).clone()),
+        }
+    }
+    match parts.len() {
+        1 => parts.remove(0),
+        // No parts at all is a word that expands to nothing
+        _ => Tree::Word(parts),
+    }
+}
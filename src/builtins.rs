@@ -0,0 +1,306 @@
+//! Commands implemented by the shell itself.
+use crate::exec::{Flow, Options, Shell};
+use crate::lexer::is_name;
+
+use std::io::Write;
+
+/// A builtin receives the shell it runs in and its arguments, without the
+/// command name, and returns an exit status.
+pub type Builtin = fn(&mut Shell, &[String]) -> i32;
+
+/// Looks up the builtin called `name`.
+pub fn lookup(name: &str) -> Option<Builtin> {
+    match name {
+        "break" => Some(break_loop),
+        "continue" => Some(continue_loop),
+        "exit" => Some(exit),
+        "export" => Some(export),
+        "local" => Some(local),
+        "return" => Some(return_from),
+        "set" => Some(set),
+        "shopt" => Some(shopt),
+        "unset" => Some(unset),
+        _ => None,
+    }
+}
+
+/// `break [n]`: leave the `n` innermost loops, or just the innermost one.
+fn break_loop(shell: &mut Shell, args: &[String]) -> i32 {
+    loop_control(shell, "break", args, Flow::Break)
+}
+
+/// `continue [n]`: start the next iteration of the `n`th innermost loop.
+fn continue_loop(shell: &mut Shell, args: &[String]) -> i32 {
+    loop_control(shell, "continue", args, Flow::Continue)
+}
+
+fn loop_control(shell: &mut Shell, name: &str, args: &[String], flow: fn(usize) -> Flow) -> i32 {
+    let n = match args {
+        [] => 1,
+        [n] => match n.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            Ok(_) => {
+                shell
+                    .io
+                    .error(format!("{name}: {n}: loop count out of range"));
+                return 1;
+            }
+            Err(_) => {
+                shell
+                    .io
+                    .error(format!("{name}: {n}: numeric argument required"));
+                return 128;
+            }
+        },
+        _ => {
+            shell.io.error(format!("{name}: too many arguments"));
+            return 1;
+        }
+    };
+    if shell.loops == 0 {
+        shell.io.error(format!(
+            "{name}: only meaningful in a `for', `while', or `until' loop"
+        ));
+        return 0;
+    }
+    // Leaving more loops than there are leaves all of them
+    shell.flow = Some(flow(n.min(shell.loops)));
+    0
+}
+
+/// `local name[=value] ...`: make variables that only last until the
+/// function being run returns.
+fn local(shell: &mut Shell, args: &[String]) -> i32 {
+    if shell.env.depth() == 0 {
+        shell.io.error("local: can only be used in a function");
+        return 1;
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !is_name(name) {
+            shell
+                .io
+                .error(format!("local: `{arg}': not a valid identifier"));
+            status = 1;
+            continue;
+        }
+        shell.env.make_local(name);
+        if let Some(value) = value {
+            shell.env.set(name, value);
+        }
+    }
+    status
+}
+
+/// `return [n]`: leave the function being run, with the status `n` or that
+/// of the last command.
+fn return_from(shell: &mut Shell, args: &[String]) -> i32 {
+    let status = match args {
+        [] => shell.status,
+        [n] => match n.parse::<i32>() {
+            Ok(n) => n & 0xff,
+            Err(_) => {
+                shell
+                    .io
+                    .error(format!("return: {n}: numeric argument required"));
+                return 2;
+            }
+        },
+        _ => {
+            shell.io.error("return: too many arguments");
+            return 1;
+        }
+    };
+    if shell.env.depth() == 0 {
+        shell.io.error("return: can only `return' from a function");
+        return 1;
+    }
+    shell.flow = Some(Flow::Return);
+    status
+}
+
+/// `exit [n]`: leave the shell with the status `n` or that of the last
+/// command. In a subshell, only the subshell is left.
+fn exit(shell: &mut Shell, args: &[String]) -> i32 {
+    let status = match args {
+        [] => shell.status,
+        [n] => match n.parse::<i32>() {
+            Ok(n) => n & 0xff,
+            Err(_) => {
+                shell
+                    .io
+                    .error(format!("exit: {n}: numeric argument required"));
+                2
+            }
+        },
+        _ => {
+            shell.io.error("exit: too many arguments");
+            return 1;
+        }
+    };
+    shell.flow = Some(Flow::Exit(status));
+    status
+}
+
+/// `set [-o|+o] [option]`: enable (`-o`) or disable (`+o`) a shell option,
+/// or list the options when none is given.
+///
+/// `set -- [arg ...]` replaces the positional parameters.
+fn set(shell: &mut Shell, args: &[String]) -> i32 {
+    let options = &mut shell.options;
+    match args {
+        [dashes, rest @ ..] if dashes == "--" => {
+            shell.args = rest.to_vec();
+            0
+        }
+        [] | [_] if args.iter().all(|a| a == "-o" || a == "+o") => {
+            let mut out = shell.io.stdout.as_ref();
+            let _ = writeln!(
+                out,
+                "pipefail\t{}",
+                if options.pipefail { "on" } else { "off" }
+            );
+            0
+        }
+        [flag, name] if flag == "-o" || flag == "+o" => {
+            let enable = flag == "-o";
+            match name.as_str() {
+                "pipefail" => options.pipefail = enable,
+                _ => {
+                    let _ = writeln!(
+                        shell.io.stderr.as_ref(),
+                        "rush: set: {name}: invalid option name"
+                    );
+                    return 2;
+                }
+            }
+            0
+        }
+        _ => {
+            let _ = writeln!(
+                shell.io.stderr.as_ref(),
+                "rush: set: usage: set [-o|+o] [option] or set -- [arg ...]"
+            );
+            2
+        }
+    }
+}
+
+/// `export [name[=value] ...]`: pass variables on to the programs the shell
+/// runs, or list the exported variables when none are given.
+fn export(shell: &mut Shell, args: &[String]) -> i32 {
+    if args.is_empty() {
+        let mut vars: Vec<_> = shell.env.exported().collect();
+        vars.sort();
+        let mut out = shell.io.stdout.as_ref();
+        for (name, value) in vars {
+            let _ = writeln!(out, "export {name}='{}'", value.replace('\'', r"'\''"));
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !is_name(name) {
+            shell
+                .io
+                .error(format!("export: `{arg}': not a valid identifier"));
+            status = 1;
+            continue;
+        }
+        if let Some(value) = value {
+            shell.env.set(name, value);
+        }
+        shell.env.export(name);
+    }
+    status
+}
+
+/// `unset name ...`: remove variables.
+fn unset(shell: &mut Shell, args: &[String]) -> i32 {
+    let mut status = 0;
+    for name in args {
+        match is_name(name) {
+            true => shell.env.unset(name),
+            false => {
+                shell
+                    .io
+                    .error(format!("unset: `{name}': not a valid identifier"));
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+/// `shopt [-s|-u] [option ...]`: enable (`-s`) or disable (`-u`) the
+/// globbing options, or show them.
+fn shopt(shell: &mut Shell, args: &[String]) -> i32 {
+    let (enable, names) = match args.first().map(String::as_str) {
+        Some("-s") => (Some(true), &args[1..]),
+        Some("-u") => (Some(false), &args[1..]),
+        Some(flag) if flag.starts_with('-') => {
+            shell.io.error(format!("shopt: {flag}: invalid option"));
+            shell.io.error("shopt: usage: shopt [-s|-u] [optname ...]");
+            return 2;
+        }
+        _ => (None, args),
+    };
+    let names: Vec<&str> = match names.is_empty() {
+        true => SHOPT_OPTIONS.to_vec(),
+        false => names.iter().map(String::as_str).collect(),
+    };
+
+    let mut status = 0;
+    for name in names {
+        let Some(option) = shopt_option(&mut shell.options, name) else {
+            shell
+                .io
+                .error(format!("shopt: {name}: invalid shell option name"));
+            status = 1;
+            continue;
+        };
+        match enable {
+            Some(enable) => *option = enable,
+            None => {
+                let state = if *option { "on" } else { "off" };
+                let _ = writeln!(shell.io.stdout.as_ref(), "{name}\t{state}");
+                // Querying options by name reports whether they are all set
+                if !*option && !args.is_empty() {
+                    status = 1;
+                }
+            }
+        }
+    }
+    status
+}
+
+const SHOPT_OPTIONS: [&str; 6] = [
+    "dotglob",
+    "extglob",
+    "failglob",
+    "globstar",
+    "nocaseglob",
+    "nullglob",
+];
+
+fn shopt_option<'a>(options: &'a mut Options, name: &str) -> Option<&'a mut bool> {
+    match name {
+        "dotglob" => Some(&mut options.dotglob),
+        "extglob" => Some(&mut options.extglob),
+        "failglob" => Some(&mut options.failglob),
+        "globstar" => Some(&mut options.globstar),
+        "nocaseglob" => Some(&mut options.nocaseglob),
+        "nullglob" => Some(&mut options.nullglob),
+        _ => None,
+    }
+}
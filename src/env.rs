@@ -0,0 +1,128 @@
+//! The variables of a shell, and which of them its children inherit.
+use std::collections::HashMap;
+use std::env;
+
+/// Shell variables, some of which are exported to the environment of the
+/// programs the shell runs.
+#[derive(Debug, Clone, Default)]
+pub struct Env {
+    vars: HashMap<String, Var>,
+    /// For each function being run, the variables it made local along with
+    /// what they were before
+    scopes: Vec<Vec<(String, Option<Var>)>>,
+}
+
+/// A variable as it was before a change, put back with `Env::restore`.
+#[derive(Debug, Clone)]
+pub struct Saved {
+    name: String,
+    var: Option<Var>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Var {
+    /// `None` for a variable that was exported before it was given a value.
+    value: Option<String>,
+    exported: bool,
+}
+
+impl Env {
+    /// Starts with every variable of the current process, all exported.
+    pub fn inherit() -> Self {
+        let vars = env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .map(|(name, value)| {
+                let var = Var {
+                    value: Some(value),
+                    exported: true,
+                };
+                (name, var)
+            })
+            .collect();
+        Self {
+            vars,
+            scopes: vec![],
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name)?.value.as_deref()
+    }
+
+    /// Sets `name` to `value`, keeping it exported if it already was.
+    pub fn set(&mut self, name: &str, value: impl Into<String>) {
+        self.vars.entry(name.to_string()).or_default().value = Some(value.into());
+    }
+
+    /// Marks `name` to be passed on to child processes.
+    pub fn export(&mut self, name: &str) {
+        self.vars.entry(name.to_string()).or_default().exported = true;
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.vars.remove(name);
+    }
+
+    /// The variables children inherit, in no particular order.
+    pub fn exported(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.iter().filter_map(|(name, var)| match var {
+            Var {
+                value: Some(value),
+                exported: true,
+            } => Some((name.as_str(), value.as_str())),
+            _ => None,
+        })
+    }
+
+    /// What `name` is now, including whether it is set or exported.
+    pub fn save(&self, name: &str) -> Saved {
+        Saved {
+            name: name.to_string(),
+            var: self.vars.get(name).cloned(),
+        }
+    }
+
+    /// Puts back a variable as it was when it was saved.
+    pub fn restore(&mut self, saved: Saved) {
+        match saved.var {
+            Some(var) => self.vars.insert(saved.name, var),
+            None => self.vars.remove(&saved.name),
+        };
+    }
+
+    /// Starts a scope for local variables, such as for a function call.
+    pub fn push_scope(&mut self) {
+        self.scopes.push(vec![]);
+    }
+
+    /// Ends the innermost scope, giving back the values its local variables
+    /// hid.
+    pub fn pop_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        for (name, var) in scope.into_iter().rev() {
+            match var {
+                Some(var) => self.vars.insert(name, var),
+                None => self.vars.remove(&name),
+            };
+        }
+    }
+
+    /// The number of scopes currently open.
+    pub fn depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    /// Makes `name` local to the innermost scope, leaving it unset until it
+    /// is given a value. Returns false if there is no scope.
+    pub fn make_local(&mut self, name: &str) -> bool {
+        let Some(scope) = self.scopes.last_mut() else {
+            return false;
+        };
+        if scope.iter().all(|(local, _)| local != name) {
+            scope.push((name.to_string(), self.vars.remove(name)));
+        }
+        true
+    }
+}
@@ -0,0 +1,812 @@
+use crate::builtins;
+use crate::env::{Env, Saved};
+use crate::glob;
+use crate::parser::{CaseArm, CaseEnd, RedirectOp, Redirection, Tree};
+use crate::span::Spanned;
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::fd::{AsFd, AsRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::ExitStatusExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::process::{self, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::thread;
+
+/// Exit status reported when a command cannot be found on `$PATH`.
+pub const NOT_FOUND: i32 = 127;
+/// Exit status reported when a command was found but could not be run.
+pub const NOT_EXECUTABLE: i32 = 126;
+
+/// The state of a running shell.
+#[derive(Debug, Clone)]
+pub struct Shell {
+    /// Exit status of the most recently executed command.
+    pub status: i32,
+    /// `$0`, the name of the shell.
+    pub name: String,
+    /// The positional parameters `$1`, `$2`, ...
+    pub args: Vec<String>,
+    /// `$!`, the process ID of the most recent background command.
+    pub background_pid: Option<u32>,
+    pub env: Env,
+    pub options: Options,
+    pub io: Io,
+    /// A `break`, `continue` or `return` that is still making its way out
+    /// of loops and functions.
+    pub flow: Option<Flow>,
+    /// How many loops the running command is inside of.
+    pub loops: usize,
+    /// The bodies of the functions defined so far, by name.
+    pub functions: HashMap<String, Arc<Tree<Spanned>>>,
+    /// Whether this is a forked copy of another shell, such as a subshell or
+    /// a stage of a pipeline.
+    pub forked: bool,
+}
+
+/// A change in the order commands run, made by a builtin such as `break`.
+///
+/// Commands after it are skipped until it is handled by the loop it names.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flow {
+    /// Leave the `n` innermost loops
+    Break(usize),
+    /// Start the next iteration of the `n`th innermost loop
+    Continue(usize),
+    /// Leave the function being run
+    Return,
+    /// Leave the shell, or the subshell it runs in, with the given status
+    Exit(i32),
+}
+
+/// Behaviour toggled with `set -o` and `shopt`.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// A pipeline fails with the status of its last failing stage.
+    pub pipefail: bool,
+    /// `**` matches any number of directories.
+    pub globstar: bool,
+    /// Patterns may use `?(..)`, `*(..)`, `+(..)`, `@(..)` and `!(..)`.
+    pub extglob: bool,
+    /// A pattern that matches no files expands to nothing.
+    pub nullglob: bool,
+    /// A pattern that matches no files is an error.
+    pub failglob: bool,
+    /// Patterns match names starting with a `.`.
+    pub dotglob: bool,
+    /// Patterns match file names without regard to case.
+    pub nocaseglob: bool,
+}
+
+/// The standard streams commands run by the shell are connected to.
+#[derive(Debug, Clone)]
+pub struct Io {
+    pub stdin: Arc<File>,
+    pub stdout: Arc<File>,
+    pub stderr: Arc<File>,
+}
+
+impl Io {
+    /// Duplicates the standard streams of the current process.
+    pub fn inherit() -> io::Result<Self> {
+        Ok(Self {
+            stdin: Arc::new(io::stdin().as_fd().try_clone_to_owned()?.into()),
+            stdout: Arc::new(io::stdout().as_fd().try_clone_to_owned()?.into()),
+            stderr: Arc::new(io::stderr().as_fd().try_clone_to_owned()?.into()),
+        })
+    }
+
+    fn stdio(file: &File) -> io::Result<Stdio> {
+        Ok(file.try_clone()?.into())
+    }
+
+    fn fd(&self, fd: u32) -> io::Result<&Arc<File>> {
+        match fd {
+            0 => Ok(&self.stdin),
+            1 => Ok(&self.stdout),
+            2 => Ok(&self.stderr),
+            _ => Err(bad_fd(fd)),
+        }
+    }
+
+    fn fd_mut(&mut self, fd: u32) -> io::Result<&mut Arc<File>> {
+        match fd {
+            0 => Ok(&mut self.stdin),
+            1 => Ok(&mut self.stdout),
+            2 => Ok(&mut self.stderr),
+            _ => Err(bad_fd(fd)),
+        }
+    }
+
+    /// Reports an error on stderr, prefixed with the shell's name.
+    pub fn error(&self, msg: impl Display) {
+        let _ = writeln!(self.stderr.as_ref(), "rush: {msg}");
+    }
+}
+
+/// A command that has been started but not yet waited on.
+enum Process {
+    Child(String, process::Child),
+    /// A copy of the shell in a child process of its own
+    Forked(libc::pid_t),
+    Done(i32),
+}
+
+impl Process {
+    /// Waits for the command to finish, reporting any error on `io`.
+    fn wait(self, io: &Io) -> i32 {
+        match self {
+            Process::Child(name, mut child) => match child.wait() {
+                Ok(status) => exit_code(status),
+                Err(e) => {
+                    io.error(format!("{name}: {e}"));
+                    1
+                }
+            },
+            Process::Forked(pid) => loop {
+                let mut status = 0;
+                // SAFETY: `pid` is a child of this process, and `status` is
+                // valid to write to
+                if unsafe { libc::waitpid(pid, &mut status, 0) } != -1 {
+                    break exit_code(ExitStatus::from_raw(status));
+                }
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    io.error(format!("wait: {e}"));
+                    break 1;
+                }
+            },
+            Process::Done(status) => status,
+        }
+    }
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        Self {
+            status: 0,
+            name: "rush".into(),
+            args: vec![],
+            background_pid: None,
+            env: Env::inherit(),
+            options: Options::default(),
+            io: Io::inherit().expect("standard streams should be available"),
+            flow: None,
+            loops: 0,
+            functions: HashMap::new(),
+            forked: false,
+        }
+    }
+
+    /// Executes `tree`, waiting for it to finish, and returns its exit status.
+    pub fn execute(&mut self, tree: &Tree<Spanned>) -> i32 {
+        self.status = match tree {
+            Tree::Command { name, args } => match self.argv(name, args) {
+                // A command made only of empty substitutions just runs them
+                Ok(argv) if argv.is_empty() => self.status,
+                // Functions come first, then builtins, then programs
+                Ok(argv) => match self.functions.get(&argv[0]).cloned() {
+                    Some(body) => self.call(&body, &argv[1..]),
+                    None => match builtins::lookup(&argv[0]) {
+                        Some(builtin) => builtin(self, &argv[1..]),
+                        None => {
+                            let status = self.spawn_program(&argv, &self.io).wait(&self.io);
+                            self.check_pipe(status)
+                        }
+                    },
+                },
+                Err(e) => {
+                    self.io.error(e);
+                    1
+                }
+            },
+            Tree::List(trees) => {
+                for tree in trees {
+                    self.execute(tree);
+                    if self.flow.is_some() {
+                        break;
+                    }
+                }
+                self.status
+            }
+            Tree::And(left, right) => match self.execute(left) {
+                0 if self.flow.is_none() => self.execute(right),
+                status => status,
+            },
+            Tree::Or(left, right) => match self.execute(left) {
+                status if status == 0 || self.flow.is_some() => status,
+                _ => self.execute(right),
+            },
+            Tree::Background(tree) => self.background(tree),
+            Tree::Pipe(..) => self.run_pipeline(tree),
+            Tree::If {
+                branches,
+                otherwise,
+            } => self.conditional(branches, otherwise.as_deref()),
+            Tree::While {
+                condition,
+                body,
+                until,
+            } => self.run_loop(|shell| shell.run_while(condition, body, *until)),
+            Tree::For { name, words, body } => {
+                self.run_loop(|shell| shell.run_for(name, words.as_deref(), body))
+            }
+            Tree::FunctionDef { name, body } => {
+                self.functions
+                    .insert(name.to_string(), Arc::new(body.as_ref().clone()));
+                0
+            }
+            Tree::Group {
+                body,
+                subshell: false,
+            } => self.execute(body),
+            // Whatever the subshell changes goes away with its process
+            Tree::Group {
+                body,
+                subshell: true,
+            } => self.fork(|shell| shell.execute(body)).wait(&self.io),
+            Tree::Case { word, arms } => match self.run_case(word, arms) {
+                Ok(status) => status,
+                Err(e) => {
+                    self.io.error(e);
+                    1
+                }
+            },
+            Tree::ArithFor {
+                init,
+                condition,
+                step,
+                body,
+            } => self.run_loop(|shell| shell.run_arith_for(init, condition, step, body)),
+            Tree::Assign { vars, command } => self.assign(vars, command.as_deref()),
+            Tree::Redirect { tree, redirects } => match self.redirect(redirects) {
+                Ok(io) => {
+                    let saved = mem::replace(&mut self.io, io);
+                    let status = self.execute(tree);
+                    self.io = saved;
+                    status
+                }
+                Err(e) => {
+                    self.io.error(e);
+                    1
+                }
+            },
+            _ => {
+                self.io
+                    .error(format!("unsupported command: {}", tree.source()));
+                2
+            }
+        };
+        // Whatever was running when `exit` was called, the shell leaves
+        // with its status
+        if let Some(Flow::Exit(status)) = self.flow {
+            self.status = status;
+        }
+        self.status
+    }
+
+    /// Passes on the status of a program, leaving a forked shell if the
+    /// program was killed for writing to a pipe that nothing reads anymore.
+    ///
+    /// Whatever the shell writes next would most likely go to the same pipe,
+    /// so it stops there rather than running more commands for nothing, as
+    /// in `while true; do echo y; done | head -1`.
+    fn check_pipe(&mut self, status: i32) -> i32 {
+        if self.forked && status == 128 + libc::SIGPIPE {
+            self.flow = Some(Flow::Exit(status));
+        }
+        status
+    }
+
+    /// Runs the function `body` with `args` as its positional parameters and
+    /// a scope of its own for local variables.
+    fn call(&mut self, body: &Tree<Spanned>, args: &[String]) -> i32 {
+        let saved = mem::replace(&mut self.args, args.to_vec());
+        self.env.push_scope();
+        let status = self.execute(body);
+        self.env.pop_scope();
+        self.args = saved;
+        if self.flow == Some(Flow::Return) {
+            self.flow = None;
+        }
+        status
+    }
+
+    /// Runs the body of the first branch whose condition succeeds, or
+    /// `otherwise` if none does.
+    fn conditional(
+        &mut self,
+        branches: &[(Tree<Spanned>, Tree<Spanned>)],
+        otherwise: Option<&Tree<Spanned>>,
+    ) -> i32 {
+        for (condition, body) in branches {
+            let status = self.execute(condition);
+            if self.flow.is_some() {
+                return status;
+            }
+            if status == 0 {
+                return self.execute(body);
+            }
+        }
+        otherwise.map_or(0, |otherwise| self.execute(otherwise))
+    }
+
+    /// Runs a loop, letting `break` and `continue` know they are inside one.
+    fn run_loop(&mut self, run: impl FnOnce(&mut Self) -> io::Result<i32>) -> i32 {
+        self.loops += 1;
+        let status = run(self);
+        self.loops -= 1;
+        status.unwrap_or_else(|e| {
+            self.io.error(e);
+            1
+        })
+    }
+
+    /// Runs `body` for as long as `condition` succeeds, or until it does
+    /// with `until`.
+    fn run_while(
+        &mut self,
+        condition: &Tree<Spanned>,
+        body: &Tree<Spanned>,
+        until: bool,
+    ) -> io::Result<i32> {
+        let mut status = 0;
+        loop {
+            let succeeded = self.execute(condition) == 0;
+            if self.flow.is_none() {
+                if succeeded == until {
+                    break;
+                }
+                status = self.execute(body);
+            }
+            if self.leave_loop() {
+                break;
+            }
+        }
+        Ok(status)
+    }
+
+    /// Runs `body` with `name` set to each field of `words` in turn, or to
+    /// each positional parameter.
+    fn run_for(
+        &mut self,
+        name: &str,
+        words: Option<&[Tree<Spanned>]>,
+        body: &Tree<Spanned>,
+    ) -> io::Result<i32> {
+        let values = match words {
+            Some(words) => {
+                let mut values = vec![];
+                for word in words {
+                    values.extend(self.expand(word)?);
+                }
+                values
+            }
+            None => self.args.clone(),
+        };
+
+        let mut status = 0;
+        for value in values {
+            self.env.set(name, value);
+            status = self.execute(body);
+            if self.leave_loop() {
+                break;
+            }
+        }
+        Ok(status)
+    }
+
+    /// Runs a C-style for loop. An empty condition is always true.
+    fn run_arith_for(
+        &mut self,
+        init: &Tree<Spanned>,
+        condition: &Tree<Spanned>,
+        step: &Tree<Spanned>,
+        body: &Tree<Spanned>,
+    ) -> io::Result<i32> {
+        let mut status = 0;
+        let init = self.expand_quoted(init)?;
+        self.evaluate(&init)?;
+        loop {
+            let condition = self.expand_quoted(condition)?;
+            if !condition.trim().is_empty() && self.evaluate(&condition)? == 0 {
+                break;
+            }
+            status = self.execute(body);
+            if self.leave_loop() {
+                break;
+            }
+            let step = self.expand_quoted(step)?;
+            self.evaluate(&step)?;
+        }
+        Ok(status)
+    }
+
+    /// Runs the body of the first arm with a pattern matching `word`, then
+    /// carries on as its `;;`, `;&` or `;;&` says.
+    fn run_case(&mut self, word: &Tree<Spanned>, arms: &[CaseArm<Spanned>]) -> io::Result<i32> {
+        let word = self.expand_quoted(word)?;
+        let mut status = 0;
+        let mut fall_through = false;
+        for arm in arms {
+            if !fall_through && !self.case_matches(&word, &arm.patterns)? {
+                continue;
+            }
+            status = self.execute(&arm.body);
+            if self.flow.is_some() {
+                break;
+            }
+            match arm.end {
+                CaseEnd::Break => break,
+                CaseEnd::FallThrough => fall_through = true,
+                CaseEnd::Continue => fall_through = false,
+            }
+        }
+        Ok(status)
+    }
+
+    fn case_matches(&mut self, word: &str, patterns: &[Tree<Spanned>]) -> io::Result<bool> {
+        for pattern in patterns {
+            let pattern = self.expand_pattern(pattern)?;
+            if glob::matches(&pattern, word, &self.options) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Handles a `break` or `continue` at the end of an iteration, and
+    /// returns whether the loop should stop.
+    fn leave_loop(&mut self) -> bool {
+        match self.flow {
+            None => false,
+            Some(Flow::Return | Flow::Exit(_)) => true,
+            Some(Flow::Break(n)) => {
+                self.flow = (n > 1).then(|| Flow::Break(n - 1));
+                true
+            }
+            Some(Flow::Continue(1)) => {
+                self.flow = None;
+                false
+            }
+            Some(Flow::Continue(n)) => {
+                self.flow = Some(Flow::Continue(n - 1));
+                true
+            }
+        }
+    }
+
+    /// Sets the variables of an assignment, in order.
+    ///
+    /// Assignments before a command only last as long as it runs, and are
+    /// exported to it. Any other variables the command changes keep their
+    /// new values.
+    fn assign(
+        &mut self,
+        vars: &[(Spanned, Tree<Spanned>)],
+        command: Option<&Tree<Spanned>>,
+    ) -> i32 {
+        let mut saved = vec![];
+        // Without a command, the status is that of the last substitution
+        self.status = 0;
+        for (name, value) in vars {
+            let value = match self.expand_assignment(value) {
+                Ok(value) => value,
+                Err(e) => {
+                    self.io.error(e);
+                    self.restore(saved);
+                    return 1;
+                }
+            };
+            if command.is_some() {
+                saved.push(self.env.save(name));
+                self.env.export(name);
+            }
+            self.env.set(name, value);
+        }
+
+        let Some(command) = command else {
+            return self.status;
+        };
+        let status = self.execute(command);
+        self.restore(saved);
+        status
+    }
+
+    /// Puts back the variables saved before a command's assignments, latest
+    /// first so that a name assigned twice gets its first value back.
+    fn restore(&mut self, saved: Vec<Saved>) {
+        for var in saved.into_iter().rev() {
+            self.env.restore(var);
+        }
+    }
+
+    /// Starts `tree` without waiting for it, recording the process ID of a
+    /// program in `$!`.
+    fn background(&mut self, tree: &Tree<Spanned>) -> i32 {
+        let process = self.spawn(tree, self.io.clone());
+        match &process {
+            Process::Child(_, child) => self.background_pid = Some(child.id()),
+            Process::Forked(pid) => self.background_pid = Some(*pid as u32),
+            _ => (),
+        }
+        // Waited on from another thread so it doesn't linger as a zombie
+        let io = self.io.clone();
+        thread::spawn(move || process.wait(&io));
+        0
+    }
+
+    /// Runs `tree` in a copy of the shell and returns everything it wrote to
+    /// stdout.
+    pub fn capture(&mut self, tree: &Tree<Spanned>) -> io::Result<String> {
+        let (mut reader, writer) = io::pipe()?;
+        let output = thread::spawn(move || {
+            let mut output = vec![];
+            reader.read_to_end(&mut output).map(|_| output)
+        });
+
+        let mut shell = Shell {
+            io: Io {
+                stdout: Arc::new(File::from(OwnedFd::from(writer))),
+                ..self.io.clone()
+            },
+            ..self.clone()
+        };
+        self.status = shell.execute(tree);
+        // Close our end of the pipe so the reader sees the end of the output
+        drop(shell);
+
+        let output = output.join().expect("reader thread should not panic")?;
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+
+    /// Runs every stage of a pipeline concurrently, each connected to the
+    /// next by an OS pipe.
+    fn run_pipeline(&mut self, tree: &Tree<Spanned>) -> i32 {
+        fn stages<'a>(tree: &'a Tree<Spanned>, out: &mut Vec<&'a Tree<Spanned>>) {
+            match tree {
+                Tree::Pipe(l, r) => {
+                    stages(l, out);
+                    stages(r, out);
+                }
+                _ => out.push(tree),
+            }
+        }
+        let mut pipeline = vec![];
+        stages(tree, &mut pipeline);
+
+        let mut processes = Vec::with_capacity(pipeline.len());
+        let mut stdin = self.io.stdin.clone();
+        for (i, stage) in pipeline.iter().enumerate() {
+            let (next, stdout) = if i + 1 == pipeline.len() {
+                (self.io.stdin.clone(), self.io.stdout.clone())
+            } else {
+                match io::pipe() {
+                    Ok((r, w)) => (
+                        Arc::new(File::from(OwnedFd::from(r))),
+                        Arc::new(File::from(OwnedFd::from(w))),
+                    ),
+                    Err(e) => {
+                        self.io.error(format!("pipe: {e}"));
+                        processes.push(Process::Done(1));
+                        break;
+                    }
+                }
+            };
+            let io = Io {
+                stdin,
+                stdout,
+                stderr: self.io.stderr.clone(),
+            };
+            processes.push(self.spawn(stage, io));
+            stdin = next;
+        }
+        drop(stdin);
+
+        let statuses: Vec<i32> = processes
+            .into_iter()
+            .map(|process| process.wait(&self.io))
+            .collect();
+        match self.options.pipefail {
+            true => statuses.into_iter().rfind(|s| *s != 0).unwrap_or(0),
+            false => statuses.last().copied().unwrap_or(0),
+        }
+    }
+
+    /// Starts `tree` with the given streams without waiting for it.
+    ///
+    /// Programs become child processes; anything run by the shell itself gets
+    /// its own copy of the shell in a forked child, like a subshell would.
+    fn spawn(&self, tree: &Tree<Spanned>, io: Io) -> Process {
+        let mut shell = Shell { io, ..self.clone() };
+
+        let Tree::Command { name, args } = tree else {
+            return shell.fork(|shell| shell.execute(tree));
+        };
+        let argv = match shell.argv(name, args) {
+            Ok(argv) if argv.is_empty() => return Process::Done(shell.status),
+            Ok(argv) => argv,
+            Err(e) => {
+                shell.io.error(e);
+                return Process::Done(1);
+            }
+        };
+        if let Some(body) = shell.functions.get(&argv[0]).cloned() {
+            return shell.fork(|shell| shell.call(&body, &argv[1..]));
+        }
+        match builtins::lookup(&argv[0]) {
+            Some(builtin) => shell.fork(|shell| builtin(shell, &argv[1..])),
+            None => shell.spawn_program(&argv, &shell.io),
+        }
+    }
+
+    /// Runs `run` with a copy of the shell in a forked child process, without
+    /// waiting for it, so that nothing it changes reaches this shell.
+    fn fork(&self, run: impl FnOnce(&mut Shell) -> i32) -> Process {
+        // SAFETY: the child only runs the shell's own code, and ends with
+        // `_exit` rather than returning to the frames it was forked from
+        match unsafe { libc::fork() } {
+            -1 => {
+                let e = io::Error::last_os_error();
+                self.io.error(format!("fork: {e}"));
+                Process::Done(1)
+            }
+            0 => {
+                close_other_fds(&self.io);
+                let mut shell = Shell {
+                    forked: true,
+                    ..self.clone()
+                };
+                // Nor may a panic unwind into them
+                let status = panic::catch_unwind(AssertUnwindSafe(|| run(&mut shell)));
+                // SAFETY: ends the child without running anything of the parent's
+                unsafe { libc::_exit(status.unwrap_or(1)) }
+            }
+            pid => Process::Forked(pid),
+        }
+    }
+
+    /// Builds the streams for a command by applying `redirects` in order to
+    /// the shell's own.
+    fn redirect(&mut self, redirects: &[Redirection<Spanned>]) -> io::Result<Io> {
+        let mut io = self.io.clone();
+
+        for Redirection { fd, op, target } in redirects {
+            let target = self.expand_one(target)?;
+            let context = |e: io::Error| io::Error::new(e.kind(), format!("{target}: {e}"));
+            let file = match op {
+                RedirectOp::Read => Arc::new(File::open(&target).map_err(context)?),
+                RedirectOp::Write | RedirectOp::WriteAll => {
+                    Arc::new(File::create(&target).map_err(context)?)
+                }
+                RedirectOp::Append | RedirectOp::AppendAll => Arc::new(
+                    OpenOptions::new()
+                        .append(true)
+                        .create(true)
+                        .open(&target)
+                        .map_err(context)?,
+                ),
+                RedirectOp::Duplicate => match target.parse() {
+                    Ok(source) => io.fd(source)?.clone(),
+                    Err(_) => {
+                        return Err(context(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "ambiguous redirect",
+                        )))
+                    }
+                },
+                RedirectOp::HereDoc => document(target)?,
+                RedirectOp::HereString => document(target + "\n")?,
+            };
+
+            match op {
+                RedirectOp::WriteAll | RedirectOp::AppendAll => {
+                    io.stdout = file.clone();
+                    io.stderr = file;
+                }
+                _ => *io.fd_mut(*fd)? = file,
+            }
+        }
+        Ok(io)
+    }
+
+    /// Starts the program named by `argv[0]`, searching `$PATH` for it.
+    fn spawn_program(&self, argv: &[String], io: &Io) -> Process {
+        let Some(path) = resolve(&argv[0], self.env.get("PATH").unwrap_or_default()) else {
+            io.error(format!("{}: command not found", argv[0]));
+            return Process::Done(NOT_FOUND);
+        };
+
+        let child = Io::stdio(&io.stdin).and_then(|stdin| {
+            process::Command::new(path)
+                .args(&argv[1..])
+                .env_clear()
+                .envs(self.env.exported())
+                .stdin(stdin)
+                .stdout(Io::stdio(&io.stdout)?)
+                .stderr(Io::stdio(&io.stderr)?)
+                .spawn()
+        });
+        match child {
+            Ok(child) => Process::Child(argv[0].clone(), child),
+            Err(e) => {
+                io.error(format!("{}: {e}", argv[0]));
+                Process::Done(match e.kind() {
+                    io::ErrorKind::NotFound => NOT_FOUND,
+                    _ => NOT_EXECUTABLE,
+                })
+            }
+        }
+    }
+}
+
+/// Makes a stream that reads back `text`, as the input of a here-document.
+fn document(text: String) -> io::Result<Arc<File>> {
+    let (reader, mut writer) = io::pipe()?;
+    // Written from another thread so a document larger than the pipe's buffer
+    // can't block the shell
+    thread::spawn(move || writer.write_all(text.as_bytes()));
+    Ok(Arc::new(File::from(OwnedFd::from(reader))))
+}
+
+/// Closes the files a forked child got from its parent other than the
+/// standard streams and those of `io`, such as the ends of pipes between
+/// other commands, which would otherwise be kept open by the child.
+fn close_other_fds(io: &Io) {
+    let keep = [&io.stdin, &io.stdout, &io.stderr].map(|file| file.as_raw_fd());
+    let Ok(dir) = fs::read_dir("/dev/fd") else {
+        return;
+    };
+    let fds: Vec<RawFd> = dir
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    for fd in fds {
+        if fd > 2 && !keep.contains(&fd) {
+            // SAFETY: the child never uses the files of its parent again
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+/// The error for a file descriptor other than stdin, stdout or stderr.
+fn bad_fd(fd: u32) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{fd}: bad file descriptor"),
+    )
+}
+
+/// Finds the program called `name` in the directories of `path`, unless
+/// `name` is already a path.
+pub fn resolve(name: &str, path: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name));
+    }
+
+    env::split_paths(path)
+        .map(|dir| dir.join(name))
+        .find(|path| {
+            path.metadata()
+                .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+        })
+}
+
+/// Maps a child's exit status to a shell status, using `128 + n` for signal `n`.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .unwrap_or_else(|| 128 + status.signal().unwrap_or_default())
+}
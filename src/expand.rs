@@ -0,0 +1,505 @@
+//! Turning the words of a parsed command into the fields passed to it.
+use crate::brace;
+use crate::exec::{Options, Shell};
+use crate::glob;
+use crate::lexer::is_name;
+use crate::parser::{ParamOp, Tree};
+use crate::span::Spanned;
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::mem;
+use std::ptr;
+
+/// Characters that separate the fields of an unquoted expansion, unless
+/// `$IFS` says otherwise.
+const IFS: &str = " \t\n";
+
+impl Shell {
+    /// Expands the name and arguments of a command into its argument vector.
+    pub fn argv(
+        &mut self,
+        name: &Tree<Spanned>,
+        args: &[Tree<Spanned>],
+    ) -> io::Result<Vec<String>> {
+        let mut argv = vec![];
+        for word in std::iter::once(name).chain(args) {
+            argv.extend(self.expand(word)?);
+        }
+        Ok(argv)
+    }
+
+    /// Expands a single word into zero or more fields.
+    ///
+    /// Braces expand first, making several words out of one. The results of
+    /// unquoted substitutions are then split on the characters of `$IFS`,
+    /// while anything inside quotes stays part of a single field.
+    pub fn expand(&mut self, word: &Tree<Spanned>) -> io::Result<Vec<String>> {
+        let mut fields = vec![];
+        for word in brace::expand(word) {
+            fields.extend(self.expand_word(&word)?);
+        }
+        Ok(fields)
+    }
+
+    /// Expands a word after brace expansion.
+    fn expand_word(&mut self, word: &Tree<Spanned>) -> io::Result<Vec<String>> {
+        let parts = match word {
+            Tree::Word(parts) => parts.as_slice(),
+            _ => std::slice::from_ref(word),
+        };
+
+        let ifs = self.env.get("IFS").unwrap_or(IFS).to_string();
+        let mut fields = Fields::default();
+        for (i, part) in parts.iter().enumerate() {
+            match part {
+                // A `~` starting the word ends at the first slash, and only
+                // expands when none of its name is quoted
+                Tree::Literal(text) | Tree::Glob(text)
+                    if i == 0 && (parts.len() == 1 || text.contains('/')) =>
+                {
+                    let (home, rest) = match self.tilde(text) {
+                        Some((home, rest)) => (home, rest),
+                        None => (String::new(), text.as_str()),
+                    };
+                    fields.quoted(&home);
+                    match part {
+                        Tree::Glob(_) => fields.unquoted(rest),
+                        _ => fields.quoted(rest),
+                    }
+                }
+                // Each positional parameter is split on its own
+                Tree::Identifier(id) if id == "@" || id == "*" => {
+                    for (i, arg) in self.args.iter().enumerate() {
+                        if i > 0 {
+                            fields.end();
+                        }
+                        fields.split(arg, &ifs);
+                    }
+                }
+                Tree::Identifier(id) => fields.split(&self.variable(id), &ifs),
+                Tree::Parameter { name, op, args } => {
+                    fields.split(&self.parameter(name, *op, args)?, &ifs)
+                }
+                Tree::Subshell(tree) => fields.split(&self.substitute(tree)?, &ifs),
+                Tree::Glob(pattern) => fields.unquoted(pattern),
+                // `"$@"` makes a field of every positional parameter, and no
+                // field at all when there are none
+                Tree::Quote(_, quoted) if quoted.iter().any(is_all_args) => {
+                    for part in quoted {
+                        match part {
+                            part if is_all_args(part) => {
+                                for (i, arg) in self.args.iter().enumerate() {
+                                    if i > 0 {
+                                        fields.end();
+                                    }
+                                    fields.quoted(arg);
+                                }
+                            }
+                            Tree::String(s) if s.is_empty() => (),
+                            Tree::String(s) => fields.quoted(s),
+                            _ => fields.quoted(&self.expand_quoted(part)?),
+                        }
+                    }
+                }
+                _ => fields.quoted(&self.expand_quoted(part)?),
+            }
+        }
+        fields.end();
+
+        // Fields with unquoted pattern characters are replaced by the files
+        // they match
+        let mut expanded = vec![];
+        for (field, pattern) in fields.done {
+            if !glob::is_pattern(&pattern, self.options.extglob) {
+                expanded.push(field);
+                continue;
+            }
+            match glob::expand(&pattern, &self.options) {
+                paths if !paths.is_empty() => expanded.extend(paths),
+                _ if self.options.failglob => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no match: {field}"),
+                    ))
+                }
+                _ if self.options.nullglob => (),
+                _ => expanded.push(field),
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Expands a word that must produce exactly one field, such as the target
+    /// of a redirection.
+    pub fn expand_one(&mut self, word: &Tree<Spanned>) -> io::Result<String> {
+        match self.expand(word)?.as_mut_slice() {
+            [field] => Ok(std::mem::take(field)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: ambiguous redirect", word.source()),
+            )),
+        }
+    }
+
+    /// Expands a word without field splitting, as if it were double quoted.
+    pub fn expand_quoted(&mut self, word: &Tree<Spanned>) -> io::Result<String> {
+        match word {
+            Tree::Literal(lit) | Tree::Glob(lit) => Ok(lit.to_string()),
+            Tree::String(s) => Ok(s[1..s.len() - 1].to_string()),
+            Tree::Quote(_, parts) => parts
+                .iter()
+                .map(|part| match part {
+                    // Unlike a whole quoted word, the strings inside a quote
+                    // don't keep their quotation marks
+                    Tree::String(s) => Ok(s.to_string()),
+                    _ => self.expand_quoted(part),
+                })
+                .collect(),
+            Tree::Subshell(tree) => self.substitute(tree),
+            Tree::Identifier(id) => Ok(self.variable(id)),
+            Tree::Parameter { name, op, args } => self.parameter(name, *op, args),
+            Tree::Word(parts) => parts.iter().map(|part| self.expand_quoted(part)).collect(),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("cannot expand {}", word.source()),
+            )),
+        }
+    }
+
+    /// Expands the value of an assignment, in which a `~` may start the
+    /// value or follow any unquoted `:`.
+    pub fn expand_assignment(&mut self, value: &Tree<Spanned>) -> io::Result<String> {
+        let parts = match value {
+            Tree::Word(parts) => parts.as_slice(),
+            _ => std::slice::from_ref(value),
+        };
+        let mut expanded = String::new();
+        for (i, part) in parts.iter().enumerate() {
+            let (Tree::Literal(text) | Tree::Glob(text)) = part else {
+                expanded.push_str(&self.expand_quoted(part)?);
+                continue;
+            };
+            let segments: Vec<&str> = text.split(':').collect();
+            for (j, segment) in segments.iter().enumerate() {
+                if j > 0 {
+                    expanded.push(':');
+                }
+                let at_start = i == 0 || j > 0;
+                let ended = segment.contains('/') || j + 1 < segments.len() || i + 1 == parts.len();
+                match self.tilde(segment).filter(|_| at_start && ended) {
+                    Some((home, rest)) => {
+                        expanded.push_str(&home);
+                        expanded.push_str(rest);
+                    }
+                    None => expanded.push_str(segment),
+                }
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Splits the tilde prefix off the start of `text`, returning the
+    /// directory it names and the rest of the text.
+    ///
+    /// `~` is `$HOME`, `~+` is `$PWD`, `~-` is `$OLDPWD`, and `~user` is the
+    /// home directory of `user`. Anything else is left alone.
+    fn tilde<'a>(&self, text: &'a str) -> Option<(String, &'a str)> {
+        let text = text.strip_prefix('~')?;
+        let (prefix, rest) = text.split_at(text.find('/').unwrap_or(text.len()));
+        let dir = match prefix {
+            "" => self.env.get("HOME")?.to_string(),
+            "+" => self.env.get("PWD")?.to_string(),
+            "-" => self.env.get("OLDPWD")?.to_string(),
+            user => home_dir(user)?,
+        };
+        Some((dir, rest))
+    }
+
+    /// Expands a word into a pattern, in which only the characters that
+    /// weren't quoted keep their special meaning.
+    pub fn expand_pattern(&mut self, word: &Tree<Spanned>) -> io::Result<String> {
+        match word {
+            Tree::String(_) | Tree::Quote(..) => Ok(glob::escape(&self.expand_quoted(word)?)),
+            Tree::Word(parts) => parts.iter().map(|part| self.expand_pattern(part)).collect(),
+            _ => self.expand_quoted(word),
+        }
+    }
+
+    /// The value of the parameter `$id`, empty if it isn't set.
+    fn variable(&self, id: &str) -> String {
+        // A `$` that isn't followed by a name is just a dollar sign
+        if id.is_empty() {
+            return "$".into();
+        }
+        self.lookup(id).unwrap_or_default()
+    }
+
+    /// The value of a variable, or of a positional or special parameter.
+    fn lookup(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.status.to_string()),
+            "$" => Some(std::process::id().to_string()),
+            "!" => self.background_pid.map(|pid| pid.to_string()),
+            "#" => Some(self.args.len().to_string()),
+            "@" => Some(self.args.join(" ")),
+            // Joined with the first character of `$IFS`
+            "*" => {
+                let ifs = self.env.get("IFS").unwrap_or(IFS);
+                let sep = ifs.chars().next().map(String::from).unwrap_or_default();
+                Some(self.args.join(&sep))
+            }
+            "0" => Some(self.name.clone()),
+            _ if name.chars().all(|c| c.is_ascii_digit()) => {
+                let n: usize = name.parse().unwrap_or(usize::MAX);
+                self.args.get(n.checked_sub(1)?).cloned()
+            }
+            _ => self.env.get(name).map(String::from),
+        }
+    }
+
+    /// Applies the operator of a parameter expansion to the variable `name`.
+    fn parameter(&mut self, name: &str, op: ParamOp, args: &[Tree<Spanned>]) -> io::Result<String> {
+        let value = self.lookup(name);
+        let is_set = |null: bool| value.as_ref().is_some_and(|v| !(null && v.is_empty()));
+        let value = value.clone().unwrap_or_default();
+
+        Ok(match op {
+            ParamOp::Length if name == "@" || name == "*" => self.args.len().to_string(),
+            ParamOp::Length => value.chars().count().to_string(),
+            ParamOp::Default { null } => match is_set(null) {
+                true => value,
+                false => self.expand_quoted(&args[0])?,
+            },
+            ParamOp::Assign { null } => match is_set(null) {
+                true => value,
+                false if !is_name(name) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("${name}: cannot assign in this way"),
+                    ))
+                }
+                false => {
+                    let word = self.expand_quoted(&args[0])?;
+                    self.env.set(name, word.clone());
+                    word
+                }
+            },
+            ParamOp::Error { null } => match is_set(null) {
+                true => value,
+                false => {
+                    let message = match self.expand_quoted(&args[0])? {
+                        message if message.is_empty() => "parameter null or not set".into(),
+                        message => message,
+                    };
+                    return Err(io::Error::other(format!("{name}: {message}")));
+                }
+            },
+            ParamOp::Alternate { null } => match is_set(null) {
+                true => self.expand_quoted(&args[0])?,
+                false => String::new(),
+            },
+            ParamOp::RemovePrefix { longest } => {
+                let pattern = self.expand_pattern(&args[0])?;
+                let mut ends: Vec<usize> = boundaries(&value).collect();
+                if longest {
+                    ends.reverse();
+                }
+                match ends
+                    .into_iter()
+                    .find(|&end| glob::matches(&pattern, &value[..end], &self.options))
+                {
+                    Some(end) => value[end..].to_string(),
+                    None => value,
+                }
+            }
+            ParamOp::RemoveSuffix { longest } => {
+                let pattern = self.expand_pattern(&args[0])?;
+                let mut starts: Vec<usize> = boundaries(&value).collect();
+                if !longest {
+                    starts.reverse();
+                }
+                match starts
+                    .into_iter()
+                    .find(|&start| glob::matches(&pattern, &value[start..], &self.options))
+                {
+                    Some(start) => value[..start].to_string(),
+                    None => value,
+                }
+            }
+            ParamOp::Replace { all } => {
+                let pattern = self.expand_pattern(&args[0])?;
+                let replacement = self.expand_quoted(&args[1])?;
+                replace(&value, &pattern, &replacement, all, &self.options)
+            }
+            ParamOp::Substring => {
+                let mut number = |arg: &Tree<Spanned>| -> io::Result<i64> {
+                    let text = self.expand_quoted(arg)?;
+                    text.trim().parse().map_err(|_| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("{text}: invalid number"),
+                        )
+                    })
+                };
+                let chars: Vec<char> = value.chars().collect();
+                let len = chars.len() as i64;
+                // Negative numbers count from the end of the value
+                let from_end = |n: i64| if n < 0 { (len + n).max(0) } else { n.min(len) };
+                let start = from_end(number(&args[0])?);
+                let end = match args.get(1) {
+                    Some(arg) => match number(arg)? {
+                        length if length < 0 => from_end(length),
+                        length => (start + length).min(len),
+                    },
+                    None => len,
+                };
+                chars[start as usize..end.max(start) as usize]
+                    .iter()
+                    .collect()
+            }
+        })
+    }
+
+    /// Runs a command substitution, returning its output without the
+    /// trailing newlines.
+    fn substitute(&mut self, tree: &Tree<Spanned>) -> io::Result<String> {
+        let mut output = self.capture(tree)?;
+        output.truncate(output.trim_end_matches('\n').len());
+        Ok(output)
+    }
+}
+
+/// Whether `part` of a quote is `$@`.
+fn is_all_args(part: &Tree<Spanned>) -> bool {
+    matches!(part, Tree::Identifier(id) if id == "@")
+}
+
+/// The fields a word expands to, built up a piece at a time.
+#[derive(Default)]
+struct Fields {
+    /// Finished fields, along with the pattern to match file names against,
+    /// in which only unquoted characters are special.
+    done: Vec<(String, String)>,
+    field: Option<String>,
+    pattern: String,
+}
+
+impl Fields {
+    /// Adds text that only ever matches itself.
+    fn quoted(&mut self, text: &str) {
+        self.field.get_or_insert_default().push_str(text);
+        self.pattern.push_str(&glob::escape(text));
+    }
+
+    /// Adds text whose pattern characters match file names.
+    fn unquoted(&mut self, text: &str) {
+        self.field.get_or_insert_default().push_str(text);
+        self.pattern.push_str(text);
+    }
+
+    /// Ends the current field, if there is one.
+    fn end(&mut self) {
+        let pattern = mem::take(&mut self.pattern);
+        if let Some(field) = self.field.take() {
+            self.done.push((field, pattern));
+        }
+    }
+
+    /// Adds the result of an unquoted expansion, split on the characters of
+    /// `ifs`.
+    ///
+    /// The first piece continues the field before it, and each separator
+    /// after that ends the field.
+    fn split(&mut self, value: &str, ifs: &str) {
+        let mut pieces = value.split(|c| ifs.contains(c));
+        if let Some(first) = pieces.next().filter(|p| !p.is_empty()) {
+            self.unquoted(first);
+        }
+        for piece in pieces {
+            self.end();
+            if !piece.is_empty() {
+                self.unquoted(piece);
+            }
+        }
+    }
+}
+
+/// The byte offsets of every character boundary in `text`, including its end.
+fn boundaries(text: &str) -> impl DoubleEndedIterator<Item = usize> + '_ {
+    text.char_indices().map(|(i, _)| i).chain([text.len()])
+}
+
+/// Replaces the longest matches of `pattern` in `text`, either the first one
+/// or all of them.
+fn replace(text: &str, pattern: &str, replacement: &str, all: bool, options: &Options) -> String {
+    let mut replaced = String::new();
+    let mut rest = text;
+    while !pattern.is_empty() {
+        let found = boundaries(rest).find_map(|start| {
+            let end = boundaries(rest)
+                .rev()
+                .take_while(|&end| end > start)
+                .find(|&end| glob::matches(pattern, &rest[start..end], options))?;
+            Some((start, end))
+        });
+        let Some((start, end)) = found else {
+            break;
+        };
+        replaced.push_str(&rest[..start]);
+        replaced.push_str(replacement);
+        rest = &rest[end..];
+        if !all {
+            break;
+        }
+    }
+    replaced + rest
+}
+
+/// The home directory of `user`, from the user database, which may be more
+/// than `/etc/passwd`.
+fn home_dir(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    let mut buf: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        // SAFETY: `passwd` is plain data, for which all zeroes is valid
+        let mut entry: libc::passwd = unsafe { mem::zeroed() };
+        let mut found = ptr::null_mut();
+        // SAFETY: the pointers are all valid for the call, and `buf` is as
+        // long as it says
+        let err = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut found,
+            )
+        };
+        match err {
+            // The strings of the entry didn't fit in `buf`
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            0 if found.is_null() => return None,
+            0 => {
+                // SAFETY: `pw_dir` points into `buf`, which is still alive
+                let dir = unsafe { CStr::from_ptr(entry.pw_dir) };
+                return Some(dir.to_string_lossy().into_owned());
+            }
+            _ => return passwd_home_dir(user),
+        }
+    }
+}
+
+/// The home directory of `user`, read from `/etc/passwd` when the user
+/// database can't be looked up.
+fn passwd_home_dir(user: &str) -> Option<String> {
+    let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|line| {
+        // name:password:uid:gid:gecos:home:shell
+        let fields: Vec<&str> = line.split(':').collect();
+        match fields.as_slice() {
+            [name, _, _, _, _, home, ..] if *name == user => Some(home.to_string()),
+            _ => None,
+        }
+    })
+}
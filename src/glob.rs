@@ -0,0 +1,312 @@
+//! Shell patterns, matched against file names and by parameter expansion.
+//!
+//! `*` matches any string, `?` any single character, and `[...]` any one of
+//! the characters it lists, or any other with a leading `!` or `^`. A
+//! backslash makes the character after it match only itself.
+//!
+//! With `extglob`, `?(list)`, `*(list)`, `+(list)`, `@(list)` and `!(list)`
+//! match zero or one, zero or more, one or more, exactly one, or none of the
+//! `|`-separated patterns in `list`.
+
+use crate::exec::Options;
+
+use std::fs;
+use std::path::Path;
+
+/// The paths matching `pattern`, in sorted order.
+///
+/// Each `/`-separated component of the pattern matches the names in one
+/// directory. Names starting with a `.` are only matched by a component that
+/// starts with one too, unless `dotglob` is set. With `globstar`, a `**`
+/// component matches any number of directories.
+pub fn expand(pattern: &str, options: &Options) -> Vec<String> {
+    let matcher = Matcher {
+        extglob: options.extglob,
+        nocase: options.nocaseglob,
+    };
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+
+    let components: Vec<&str> = rest.split('/').collect();
+    for (i, component) in components.iter().enumerate() {
+        let last = i + 1 == components.len();
+        let mut next = vec![];
+        for path in paths {
+            if *component == "**" && options.globstar {
+                // The directory itself, then everything under it
+                if !last {
+                    next.push(path.clone());
+                } else if !path.is_empty() {
+                    next.push(join(&path, ""));
+                }
+                descendants(&path, !last, options.dotglob, &mut next);
+                continue;
+            }
+            if !is_pattern(component, options.extglob) {
+                // A trailing slash only matches directories
+                let joined = join(&path, &unescape(component));
+                let found = match (last, component.is_empty()) {
+                    (true, true) => Path::new(&joined).is_dir(),
+                    (true, false) => fs::symlink_metadata(&joined).is_ok(),
+                    (false, _) => true,
+                };
+                if found {
+                    next.push(joined);
+                }
+                continue;
+            }
+
+            let names = entries(&path)
+                .filter(|(name, _)| {
+                    !name.starts_with('.') || options.dotglob || component.starts_with('.')
+                })
+                .filter(|(name, _)| matcher.matches(component, name))
+                .map(|(name, _)| join(&path, &name));
+            next.extend(names);
+        }
+        paths = next;
+    }
+    paths.sort();
+    paths
+}
+
+/// Whether `pattern` matches the whole of `text`.
+pub fn matches(pattern: &str, text: &str, options: &Options) -> bool {
+    let matcher = Matcher {
+        extglob: options.extglob,
+        nocase: false,
+    };
+    matcher.matches(pattern, text)
+}
+
+/// Escapes the characters of `text` that are special in a pattern, so that
+/// it only matches itself.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if ['*', '?', '[', ']', '\\', '(', ')', '|', '+', '@', '!'].contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Whether `text` has any unescaped pattern characters.
+pub fn is_pattern(text: &str, extglob: bool) -> bool {
+    let mut escaped = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' | '?' | '[' if !escaped => return true,
+            '+' | '@' | '!' if !escaped && extglob && chars.peek() == Some(&'(') => return true,
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    false
+}
+
+/// The entries of the directory `path`, with whether each is a directory.
+fn entries(path: &str) -> impl Iterator<Item = (String, bool)> {
+    let dir = if path.is_empty() { "." } else { path };
+    fs::read_dir(dir).into_iter().flatten().filter_map(|entry| {
+        let entry = entry.ok()?;
+        // Symbolic links to directories aren't followed
+        let is_dir = entry.file_type().ok()?.is_dir();
+        Some((entry.file_name().into_string().ok()?, is_dir))
+    })
+}
+
+/// Adds everything under `path` to `out`, or only the directories if
+/// `dirs_only` is set.
+fn descendants(path: &str, dirs_only: bool, dotglob: bool, out: &mut Vec<String>) {
+    for (name, is_dir) in entries(path).filter(|(name, _)| dotglob || !name.starts_with('.')) {
+        let joined = join(path, &name);
+        if is_dir || !dirs_only {
+            out.push(joined.clone());
+        }
+        if is_dir {
+            descendants(&joined, dirs_only, dotglob, out);
+        }
+    }
+}
+
+/// Removes the backslashes of a component without pattern characters.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+fn join(dir: &str, name: &str) -> String {
+    match dir {
+        "" => name.to_string(),
+        "/" => format!("/{name}"),
+        _ => format!("{dir}/{name}"),
+    }
+}
+
+/// An extglob pattern list, such as `+(a|b)`.
+struct PatternList<'a> {
+    /// One of `?`, `*`, `+`, `@` or `!`
+    kind: char,
+    alternatives: Vec<&'a [char]>,
+    /// The rest of the pattern after the list
+    rest: &'a [char],
+}
+
+#[derive(Clone, Copy)]
+struct Matcher {
+    extglob: bool,
+    nocase: bool,
+}
+
+impl Matcher {
+    fn matches(&self, pattern: &str, text: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let text: Vec<char> = text.chars().collect();
+        self.match_from(&pattern, &text)
+    }
+
+    fn eq(&self, a: char, b: char) -> bool {
+        a == b || (self.nocase && a.to_lowercase().eq(b.to_lowercase()))
+    }
+
+    fn match_from(&self, pattern: &[char], text: &[char]) -> bool {
+        if let Some(list) = self.pattern_list(pattern) {
+            return self.match_list(&list, text);
+        }
+        match pattern {
+            [] => text.is_empty(),
+            ['*', rest @ ..] => {
+                // Several stars in a row match the same as one
+                let rest = &rest[rest.iter().take_while(|&&c| c == '*').count()..];
+                (0..=text.len()).any(|i| self.match_from(rest, &text[i..]))
+            }
+            ['?', rest @ ..] => !text.is_empty() && self.match_from(rest, &text[1..]),
+            ['[', class @ ..] => match (self.bracket(class, text.first()), text.first()) {
+                (Some((matched, rest)), Some(_)) => matched && self.match_from(rest, &text[1..]),
+                (Some(_), None) => false,
+                // An unclosed bracket is just a bracket
+                (None, _) => text.first() == Some(&'[') && self.match_from(class, &text[1..]),
+            },
+            ['\\', c, rest @ ..] | [c, rest @ ..] => {
+                text.first().is_some_and(|t| self.eq(*c, *t)) && self.match_from(rest, &text[1..])
+            }
+        }
+    }
+
+    /// Splits off the extglob pattern list, such as `+(a|b)`, at the start
+    /// of `pattern`.
+    fn pattern_list<'a>(&self, pattern: &'a [char]) -> Option<PatternList<'a>> {
+        let [kind @ ('?' | '*' | '+' | '@' | '!'), '(', list @ ..] = pattern else {
+            return None;
+        };
+        if !self.extglob {
+            return None;
+        }
+
+        let mut alternatives = vec![];
+        let (mut depth, mut start, mut escaped) = (0, 0, false);
+        for (i, c) in list.iter().enumerate() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '(' => depth += 1,
+                ')' if depth > 0 => depth -= 1,
+                ')' => {
+                    alternatives.push(&list[start..i]);
+                    return Some(PatternList {
+                        kind: *kind,
+                        alternatives,
+                        rest: &list[i + 1..],
+                    });
+                }
+                '|' if depth == 0 => {
+                    alternatives.push(&list[start..i]);
+                    start = i + 1;
+                }
+                _ => (),
+            }
+        }
+        // An unclosed list is just characters
+        None
+    }
+
+    fn match_list(&self, list: &PatternList, text: &[char]) -> bool {
+        let PatternList {
+            kind,
+            alternatives,
+            rest,
+        } = list;
+        let one = |text: &[char]| alternatives.iter().any(|alt| self.match_from(alt, text));
+        match kind {
+            '@' => (0..=text.len()).any(|i| one(&text[..i]) && self.match_from(rest, &text[i..])),
+            '?' => {
+                self.match_from(rest, text)
+                    || (0..=text.len())
+                        .any(|i| one(&text[..i]) && self.match_from(rest, &text[i..]))
+            }
+            '*' => self.match_many(alternatives, rest, text),
+            '+' => (1..=text.len())
+                .any(|i| one(&text[..i]) && self.match_many(alternatives, rest, &text[i..])),
+            // `!(list)` matches any text that none of the list matches
+            _ => (0..=text.len()).any(|i| !one(&text[..i]) && self.match_from(rest, &text[i..])),
+        }
+    }
+
+    /// Matches zero or more of `alternatives`, followed by `rest`.
+    fn match_many(&self, alternatives: &[&[char]], rest: &[char], text: &[char]) -> bool {
+        self.match_from(rest, text)
+            || (1..=text.len()).any(|i| {
+                alternatives
+                    .iter()
+                    .any(|alt| self.match_from(alt, &text[..i]))
+                    && self.match_many(alternatives, rest, &text[i..])
+            })
+    }
+
+    /// Matches `ch` against the bracket expression at the start of `class`,
+    /// just after its `[`.
+    ///
+    /// Returns whether it matched along with the rest of the pattern, or
+    /// `None` if the bracket is never closed.
+    fn bracket<'a>(&self, class: &'a [char], ch: Option<&char>) -> Option<(bool, &'a [char])> {
+        let (negated, mut class) = match class {
+            ['!' | '^', rest @ ..] => (true, rest),
+            _ => (false, class),
+        };
+
+        let mut matched = false;
+        let mut first = true;
+        loop {
+            let (lo, rest) = match class {
+                [']', rest @ ..] if !first => return Some((matched != negated, rest)),
+                ['\\', c, rest @ ..] | [c, rest @ ..] => (*c, rest),
+                [] => return None,
+            };
+            let (hi, rest) = match rest {
+                ['-', ']', ..] => (lo, rest),
+                ['-', '\\', c, rest @ ..] | ['-', c, rest @ ..] => (*c, rest),
+                _ => (lo, rest),
+            };
+            matched |= ch.is_some_and(|&ch| {
+                let in_range = |c: char| (lo..=hi).contains(&c);
+                in_range(ch)
+                    || (self.nocase
+                        && (ch.to_lowercase().all(in_range) || ch.to_uppercase().all(in_range)))
+            });
+            class = rest;
+            first = false;
+        }
+    }
+}
@@ -1,4 +1,9 @@
+use crate::span::{render, Position, Span, Spanned};
+
+use std::fmt::{self, Display};
+use std::iter::Peekable;
 use std::mem::take;
+use std::str::Chars;
 
 #[derive(Debug, Clone)] //PartialEq
 pub enum Token<T>
@@ -12,6 +17,8 @@ where
     Wildcard(T),
     Str(T),
     Comment(T),
+    /// Separates two parts of the same word, as in `$HOME/bin` or `"a"'b'`
+    Join,
 }
 
 impl<T, U> PartialEq<Token<U>> for Token<T>
@@ -29,13 +36,14 @@ where
             | (Wildcard(a), Wildcard(b))
             | (Str(a), Str(b))
             | (Comment(a), Comment(b)) => a.as_ref() == b.as_ref(),
+            (Join, Join) => true,
             _ => false,
         }
     }
 }
 
-impl Token<String> {
-    pub fn inner(&self) -> &String {
+impl Token<Spanned> {
+    pub fn inner(&self) -> &str {
         match self {
             Token::Literal(s)
             | Token::Symbol(s)
@@ -44,10 +52,12 @@ impl Token<String> {
             | Token::Wildcard(s)
             | Token::Str(s)
             | Token::Comment(s) => s,
+            Token::Join => "",
         }
     }
 
-    pub fn inner_mut(&mut self) -> &mut String {
+    /// The text of the token, if it has any.
+    pub fn inner_mut(&mut self) -> Option<&mut Spanned> {
         match self {
             Token::Literal(s)
             | Token::Symbol(s)
@@ -55,59 +65,199 @@ impl Token<String> {
             | Token::Identifier(s)
             | Token::Wildcard(s)
             | Token::Str(s)
-            | Token::Comment(s) => s,
+            | Token::Comment(s) => Some(s),
+            Token::Join => None,
+        }
+    }
+
+    /// Where the token was found, if it came from the source.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Token::Literal(s)
+            | Token::Symbol(s)
+            | Token::ControlOperator(s)
+            | Token::Identifier(s)
+            | Token::Wildcard(s)
+            | Token::Str(s)
+            | Token::Comment(s) => s.span,
+            Token::Join => None,
         }
     }
 }
 
 impl<T> Token<T>
 where
-    T: AsRef<str> + Clone + ToString,
+    T: AsRef<str> + Clone + Into<Spanned>,
 {
-    pub fn inner_to_string(&mut self) -> Token<String> {
+    /// The same token, with text that may know where it came from.
+    pub fn into_spanned(self) -> Token<Spanned> {
         match self {
-            Token::Literal(s) => Token::Literal(s.to_string()),
-            Token::Symbol(s) => Token::Symbol(s.to_string()),
-            Token::ControlOperator(s) => Token::ControlOperator(s.to_string()),
-            Token::Identifier(s) => Token::Identifier(s.to_string()),
-            Token::Wildcard(s) => Token::Wildcard(s.to_string()),
-            Token::Str(s) => Token::Str(s.to_string()),
-            Token::Comment(s) => Token::Comment(s.to_string()),
+            Token::Literal(s) => Token::Literal(s.into()),
+            Token::Symbol(s) => Token::Symbol(s.into()),
+            Token::ControlOperator(s) => Token::ControlOperator(s.into()),
+            Token::Identifier(s) => Token::Identifier(s.into()),
+            Token::Wildcard(s) => Token::Wildcard(s.into()),
+            Token::Str(s) => Token::Str(s.into()),
+            Token::Comment(s) => Token::Comment(s.into()),
+            Token::Join => Token::Join,
         }
     }
 }
 
 pub trait Tokenize {
-    fn tokenize(self) -> Vec<Token<String>>;
+    fn tokenize(self) -> Vec<Token<Spanned>>;
+    /// Like `tokenize`, but fails on input that ends in the middle of a
+    /// quote, substitution or here-document instead of tokenizing what it can.
+    fn try_tokenize(self) -> Result<Vec<Token<Spanned>>, LexError>;
 }
 
 impl<S> Tokenize for S
 where
     S: AsRef<str>,
 {
-    fn tokenize(self) -> Vec<Token<String>> {
-        let mut fsm = self
-            .as_ref()
-            .trim()
-            .chars()
-            .fold(Lexer::default(), |fsm, ch| fsm.tokenize_char(ch));
+    fn tokenize(self) -> Vec<Token<Spanned>> {
+        tokenize_from(self.as_ref(), Position::default())
+    }
 
-        if !fsm.current.is_empty() {
-            match fsm.state {
-                LexerState::InLiteral => fsm.tokens.push(Token::Literal(fsm.current)),
-                // LexerState::InQuote(_) => fsm.tokens.push(Token::Str(fsm.current)),
-                LexerState::InSubstitution => fsm.tokens.push(Token::Identifier(fsm.current)),
-                LexerState::InWildcard => fsm.tokens.push(Token::Wildcard(fsm.current)),
-                LexerState::InOperator(_) => fsm.tokens.push(Token::Symbol(fsm.current)),
-                LexerState::InComment => fsm.tokens.push(Token::Comment(fsm.current)),
-                _ => (),
-            }
+    fn try_tokenize(self) -> Result<Vec<Token<Spanned>>, LexError> {
+        try_tokenize_from(self.as_ref(), Position::default())
+    }
+}
+
+/// Why the input couldn't be tokenized: it ended before something in it was
+/// closed, so more input could still complete it. Each error holds the text
+/// that opened the construct.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LexError {
+    /// The opening quote of a string that is never closed
+    UnterminatedQuote(Spanned),
+    /// A `$(` or `${` that is never closed
+    UnterminatedSubstitution(Spanned),
+    /// A `(`, or the `((` of an arithmetic `for`, that is never closed
+    UnterminatedParen(Spanned),
+    /// The delimiter of a here-document whose body never ends
+    UnterminatedHereDoc(Spanned),
+    /// A backslash at the end of the input, continuing it on the next line
+    UnterminatedLine(Spanned),
+}
+
+impl Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnterminatedQuote(q) => write!(f, "unexpected EOF while looking for matching `{q}'"),
+            Self::UnterminatedSubstitution(open) => match open.ends_with('{') {
+                true => write!(f, "unexpected EOF while looking for matching `}}'"),
+                false => write!(f, "unexpected EOF while looking for matching `)'"),
+            },
+            Self::UnterminatedParen(open) => write!(
+                f,
+                "unexpected EOF while looking for matching `{}'",
+                ")".repeat(open.len())
+            ),
+            Self::UnterminatedHereDoc(delimiter) => write!(
+                f,
+                "here-document delimited by end-of-file (wanted `{delimiter}')"
+            ),
+            Self::UnterminatedLine(_) => write!(f, "unexpected EOF after line continuation"),
+        }
+    }
+}
+
+impl std::error::Error for LexError {}
+
+impl LexError {
+    /// The text that opened the unterminated construct.
+    pub fn opening(&self) -> &Spanned {
+        match self {
+            Self::UnterminatedQuote(text)
+            | Self::UnterminatedSubstitution(text)
+            | Self::UnterminatedParen(text)
+            | Self::UnterminatedHereDoc(text)
+            | Self::UnterminatedLine(text) => text,
         }
+    }
+
+    /// Where the unterminated construct starts.
+    pub fn start(&self) -> Position {
+        self.opening().span.unwrap_or_default().start
+    }
 
-        fsm.tokens
+    /// The error's message, followed by the line of `source` where the
+    /// unterminated construct starts.
+    pub fn render(&self, source: &str) -> String {
+        render(source, self.opening().span.unwrap_or_default(), self)
     }
 }
 
+/// Tokenizes `source`, which starts at `start` in some larger text.
+///
+/// Quotes and substitutions left open at the end of the input end with it,
+/// and so do here-documents, like bash does.
+pub fn tokenize_from(source: &str, start: Position) -> Vec<Token<Spanned>> {
+    Lexer::lex(source, start).end().finish()
+}
+
+/// Tokenizes `source` like `tokenize_from`, failing if it ends before
+/// something in it is closed.
+pub fn try_tokenize_from(source: &str, start: Position) -> Result<Vec<Token<Spanned>>, LexError> {
+    let fsm = Lexer::lex(source, start);
+    if let Some(e) = fsm.unterminated() {
+        return Err(e);
+    }
+    let fsm = fsm.end();
+    // A here-document on the last line is only looked for at its newline
+    let pending = match fsm.heredocs.is_empty() {
+        true => fsm.find_heredocs(),
+        false => fsm.heredocs,
+    };
+    if let Some(doc) = pending.into_iter().next() {
+        return Err(LexError::UnterminatedHereDoc(doc.delimiter));
+    }
+    Ok(fsm.tokens)
+}
+
+/// Tokenizes the body of a here-document whose delimiter was not quoted.
+///
+/// Only `$` is special in such a document; everything else, quotes
+/// included, is returned as `Str` tokens.
+pub fn tokenize_document(body: &str) -> Vec<Token<Spanned>> {
+    document_from(body, Position::default())
+}
+
+/// Tokenizes a document whose body starts at `start` in some larger text.
+pub fn document_from(body: &str, start: Position) -> Vec<Token<Spanned>> {
+    let lexer = Lexer {
+        state: LexerState::InDocument,
+        pos: start,
+        ..Default::default()
+    };
+    let mut fsm = body.chars().fold(lexer, Lexer::read);
+
+    if fsm.state == LexerState::InSubstitution {
+        let id = fsm.take_current();
+        fsm.tokens.push(Token::Identifier(id));
+    }
+    let rest = fsm.take_current();
+    fsm.tokens.push(Token::Str(rest));
+    fsm.tokens
+}
+
+/// Tokenizes the operand of a parameter expansion such as `${name:-word}`,
+/// which starts at `start` in some larger text.
+///
+/// Only quotes, `$` and `\` are special in an operand; the rest, spaces and
+/// operators included, is returned as `Literal` or `Wildcard` tokens joined
+/// into one word.
+pub fn operand_from(text: &str, start: Position) -> Vec<Token<Spanned>> {
+    let lexer = Lexer {
+        state: LexerState::InOperand,
+        operand: true,
+        pos: start,
+        ..Default::default()
+    };
+    text.chars().fold(lexer, Lexer::read).end().tokens
+}
+
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
 enum LexerState {
     #[default]
@@ -116,122 +266,850 @@ enum LexerState {
     InQuote(char),
     InSubstitution,
     InWildcard,
-    InOperator(char),
+    InOperator,
     InComment,
+    InHereDoc,
+    InDocument,
+    /// Inside the unquoted text of an operand read by `operand_from`.
+    InOperand,
+    /// A part of a word has ended, and another may follow right away.
+    AfterPart,
+    /// Right after the `=` of `NAME=value`.
+    InAssignment,
+    /// Inside the double parentheses of an arithmetic `for` loop.
+    InArithmetic,
+    /// Inside `$'...'`, whose escapes are decoded when it ends.
+    InAnsiQuote,
+}
+
+/// A here-document whose body is still being read.
+#[derive(Debug)]
+struct HereDoc {
+    delimiter: Spanned,
+    /// Strip leading tabs, as requested by `<<-`
+    strip_tabs: bool,
+    /// Where the body's token belongs, right after the delimiter
+    index: usize,
+    body: String,
+    /// Where the first line of the body starts
+    start: Option<Position>,
 }
 
 #[derive(Debug, Default)]
 struct Lexer {
     state: LexerState,
     stack: Vec<LexerState>,
+    /// Where the `$` or `(` that pushed each state of `stack` is
+    opened: Vec<Position>,
     current: String,
-    tokens: Vec<Token<String>>,
+    tokens: Vec<Token<Spanned>>,
+    heredocs: Vec<HereDoc>,
+    /// Index of the first token on the current line
+    line_start: usize,
+    /// The depth of the stack at each `case` that hasn't reached its `esac`
+    cases: Vec<usize>,
+    /// Where the character being read is
+    pos: Position,
+    /// Where the backslash escaping the next character is
+    backslash: Option<Position>,
+    /// Where the text in `current` starts and ends
+    start: Position,
+    end: Position,
+    /// Whether the source is an operand, which is a single word however it
+    /// is spaced
+    operand: bool,
 }
 
 impl Lexer {
+    /// Reads all of `source`, which starts at `start` in some larger text,
+    /// ignoring the whitespace around it.
+    fn lex(source: &str, start: Position) -> Self {
+        let first = source.len() - source.trim_start().len();
+        let mut last = source.trim_end().len().max(first);
+        // Keep a space escaped by a trailing backslash, though not a newline,
+        // which would continue the input on a line that isn't there
+        if source[..last].ends_with('\\') {
+            last += source[last..]
+                .chars()
+                .next()
+                .filter(|&c| c != '\n')
+                .map_or(0, char::len_utf8);
+        }
+        let lexer = Lexer {
+            pos: start.after_str(&source[..first]),
+            ..Default::default()
+        };
+        source[first..last].chars().fold(lexer, Lexer::read)
+    }
+
+    /// Ends the token being read at the end of the input.
+    fn end(mut self) -> Self {
+        if let Some(at) = self.backslash.take() {
+            // A backslash with nothing after it stands for itself
+            self = self.escape(at, '\\');
+        }
+        if self.state == LexerState::InHereDoc {
+            // Finish the last line of the document
+            self = self.tokenize_char('\n');
+        }
+
+        match self.state {
+            LexerState::InSubstitution => {
+                let id = self.take_current();
+                self.tokens.push(Token::Identifier(id));
+            }
+            LexerState::InAssignment => {
+                let value = self.take_current();
+                self.tokens.push(Token::Literal(value));
+            }
+            _ if self.current.is_empty() => (),
+            LexerState::InLiteral => self.end_literal(true),
+            LexerState::InOperand => {
+                let text = self.take_operand();
+                self.tokens.push(text);
+            }
+            LexerState::InQuote(_) => {
+                let text = self.take_current();
+                self.tokens.push(Token::Str(text));
+            }
+            LexerState::InAnsiQuote => {
+                let text = self.take_current();
+                self.tokens.push(Token::Str(decode_ansi_c(text)));
+            }
+            LexerState::InWildcard => {
+                let pattern = self.take_current();
+                self.tokens.push(Token::Wildcard(pattern));
+            }
+            LexerState::InOperator => {
+                let op = self.take_current();
+                self.tokens.push(Token::Symbol(op));
+            }
+            LexerState::InComment => {
+                let comment = self.take_current();
+                self.tokens.push(Token::Comment(comment));
+            }
+            _ => (),
+        }
+        self
+    }
+
+    /// The tokens read, after ending any documents that are still open.
+    fn finish(mut self) -> Vec<Token<Spanned>> {
+        while !self.heredocs.is_empty() {
+            self.finish_heredoc(self.pos);
+        }
+        self.tokens
+    }
+
+    /// The innermost quote or substitution left open at the end of the input.
+    fn unterminated(&self) -> Option<LexError> {
+        let opening = |text: &str, start: Position| {
+            Spanned::new(text, Span::new(start, start.after_str(text)))
+        };
+        let (state, stack, opened) = match self.state {
+            // A name at the end of the input ends with it, closing its `$`
+            LexerState::InSubstitution if !self.current.starts_with('{') => (
+                self.stack.last().copied().unwrap_or_default(),
+                &self.stack[..self.stack.len().saturating_sub(1)],
+                &self.opened[..self.opened.len().saturating_sub(1)],
+            ),
+            state => (state, &self.stack[..], &self.opened[..]),
+        };
+
+        if let Some(at) = self.backslash {
+            return Some(LexError::UnterminatedLine(opening("\\", at)));
+        }
+        match state {
+            LexerState::InQuote(_) | LexerState::InAnsiQuote => {
+                let q = match state {
+                    LexerState::InQuote(q) => q,
+                    _ => '\'',
+                };
+                // The opening quote is the last one read
+                let quote = self.tokens.iter().rev().find_map(|token| match token {
+                    Token::Symbol(sym) if sym.starts_with(q) => Some(sym.clone()),
+                    _ => None,
+                });
+                Some(LexError::UnterminatedQuote(quote.unwrap_or_else(|| q.into())))
+            }
+            LexerState::InSubstitution => {
+                let start = opened.last().copied().unwrap_or(self.start);
+                Some(LexError::UnterminatedSubstitution(opening("${", start)))
+            }
+            LexerState::InArithmetic => Some(LexError::UnterminatedParen(opening("((", self.start))),
+            // Whatever is left on the stack was opened by a `(` or `$(`
+            _ => Some(match (stack.last()?, opened.last()?) {
+                (LexerState::Start, &start) => LexError::UnterminatedParen(opening("(", start)),
+                (_, &start) => LexError::UnterminatedSubstitution(opening("$(", start)),
+            }),
+        }
+    }
+
+    /// Starts a construct opened by the character being read, which goes
+    /// back to `resume` when it is closed.
+    fn open(&mut self, resume: LexerState) {
+        self.stack.push(resume);
+        self.opened.push(self.pos);
+    }
+
+    /// Closes the innermost open construct, giving the state to go back to.
+    fn close(&mut self) -> LexerState {
+        self.opened.pop();
+        self.stack.pop().unwrap_or_default()
+    }
+
+    /// Reads the next character of the source.
+    fn read(mut self, ch: char) -> Self {
+        let mut fsm = match self.backslash.take() {
+            // A backslash before a newline joins the two lines
+            Some(_) if ch == '\n' && self.state != LexerState::InAnsiQuote => self,
+            Some(at) => self.escape(at, ch),
+            None if ch == '\\' && self.escapes() => {
+                self.backslash = Some(self.pos);
+                self
+            }
+            None => self.tokenize_char(ch),
+        };
+        fsm.pos = fsm.pos.after(ch);
+        fsm
+    }
+
+    /// Whether a backslash read now escapes the character after it.
+    fn escapes(&self) -> bool {
+        match self.state {
+            LexerState::InQuote(q) => q == '"',
+            LexerState::InComment | LexerState::InHereDoc | LexerState::InArithmetic => false,
+            // `${...}` is read whole, and tokenized again when it is parsed
+            LexerState::InSubstitution => !self.current.starts_with('{'),
+            // and so is the pattern list of an extglob, by the glob matcher
+            LexerState::InWildcard => {
+                self.current.matches('(').count() <= self.current.matches(')').count()
+            }
+            _ => true,
+        }
+    }
+
+    /// Reads `c`, escaped by the backslash at `at`.
+    fn escape(mut self, at: Position, c: char) -> Self {
+        if self.state == LexerState::InSubstitution {
+            // The backslash ends the name before it
+            let id = self.take_current();
+            self.tokens.push(Token::Identifier(id));
+            self.state = self.close();
+        }
+
+        match self.state {
+            LexerState::InQuote(_) | LexerState::InDocument | LexerState::InAnsiQuote => {
+                // Inside double quotes and documents, the backslash stays
+                // unless it escapes a character that would be special there.
+                // The escapes of `$'...'` are decoded when it ends.
+                let special = match self.state {
+                    LexerState::InQuote(_) => matches!(c, '$' | '`' | '"' | '\\'),
+                    LexerState::InDocument => matches!(c, '$' | '`' | '\\'),
+                    _ => false,
+                };
+                let empty = self.current.is_empty();
+                if !special {
+                    self.current.push('\\');
+                }
+                self.push_char(c);
+                if empty {
+                    self.start = at;
+                }
+            }
+            _ => {
+                // End the part before the backslash, then make a part of the
+                // escaped character as if it had been quoted
+                self = self.tokenize_char('\\');
+                let backslash = Spanned::new('\\', Span::new(at, at.after('\\')));
+                self.tokens.push(Token::Symbol(backslash));
+                self.tokens.push(Token::Str(self.this_char(c)));
+                self.state = LexerState::AfterPart;
+            }
+        }
+        self
+    }
+
+    /// Adds `c`, the character being read, to the current token.
+    fn push_char(&mut self, c: char) {
+        if self.current.is_empty() {
+            self.start = self.pos;
+        }
+        self.current.push(c);
+        self.end = self.pos.after(c);
+    }
+
+    /// Takes the text of the current token, along with where it was found.
+    fn take_current(&mut self) -> Spanned {
+        let span = match self.current.is_empty() {
+            true => Span::at(self.pos),
+            false => Span::new(self.start, self.end),
+        };
+        Spanned::new(take(&mut self.current), span)
+    }
+
+    /// Takes the unquoted text of an operand, which is a pattern if it has
+    /// any of the characters that start one.
+    fn take_operand(&mut self) -> Token<Spanned> {
+        let text = self.take_current();
+        match text.contains(['*', '?', '[']) {
+            true => Token::Wildcard(text),
+            false => Token::Literal(text),
+        }
+    }
+
+    /// The character being read, as the text of a token.
+    fn this_char(&self, c: char) -> Spanned {
+        Spanned::new(c, Span::new(self.pos, self.pos.after(c)))
+    }
+
     fn tokenize_char(mut self, ch: char) -> Self {
         match (self.state, ch) {
+            (LexerState::Start, '\n') => {
+                // Ends a command, like `;`
+                self.tokens.push(Token::Symbol(self.this_char(ch)));
+                self.heredocs = self.find_heredocs();
+                self.line_start = self.tokens.len();
+                if !self.heredocs.is_empty() {
+                    self.state = LexerState::InHereDoc;
+                }
+            }
             (LexerState::Start, c) if c.is_whitespace() => (),
+            // Only read by `escape`, once the part before the backslash has ended
+            (LexerState::Start, '\\') => (),
             (LexerState::Start, '\'' | '\"') => {
-                self.tokens.push(Token::Symbol(ch.into()));
+                self.tokens.push(Token::Symbol(self.this_char(ch)));
                 self.state = LexerState::InQuote(ch);
             }
-            (LexerState::Start, '>' | '<') => {
-                self.current.push(ch);
-                self.state = LexerState::InOperator(ch);
+            (LexerState::Start, '>' | '<' | '&' | '|' | ';') => {
+                self.push_char(ch);
+                self.state = LexerState::InOperator;
             }
-            (LexerState::Start, '|' | '=' | ';') => self.tokens.push(Token::Symbol(ch.into())),
             (LexerState::Start, '#') => {
-                self.current.push(ch);
+                self.push_char(ch);
                 self.state = LexerState::InComment;
             }
             (LexerState::Start, '$') => {
-                self.tokens.push(Token::Symbol('$'.into()));
+                self.tokens.push(Token::Symbol(self.this_char(ch)));
+                self.open(LexerState::AfterPart);
                 self.state = LexerState::InSubstitution;
             }
-            (LexerState::Start | LexerState::InSubstitution | LexerState::InOperator(_), '(') => {
-                self.tokens.push(Token::Symbol('('.into()));
+            (LexerState::Start, '(')
+                if matches!(self.tokens.last(), Some(Token::ControlOperator(w)) if w == "for") =>
+            {
+                // The `((init; condition; step))` of a C-style for loop
+                self.push_char(ch);
+                self.state = LexerState::InArithmetic;
+            }
+            (LexerState::Start, '(') => {
+                // Anywhere else, such as in `echo a(b`, the `(` opens nothing
+                // and is left for the parser to reject
+                if self.opens_paren() {
+                    self.open(LexerState::Start);
+                }
+                self.tokens.push(Token::Symbol(self.this_char(ch)));
+            }
+            (LexerState::InSubstitution, '(') if self.current.is_empty() => {
+                self.tokens.push(Token::Symbol(self.this_char(ch)));
                 self.state = LexerState::Start;
             }
-            (LexerState::Start | LexerState::InSubstitution | LexerState::InLiteral, ')') => {
-                self.tokens.push(Token::Literal(take(&mut self.current)));
-                self.tokens.push(Token::Symbol(')'.into()));
-                self.state = self.stack.pop().unwrap_or_default();
+            (LexerState::Start, ')') => {
+                self.tokens.push(Token::Symbol(self.this_char(ch)));
+                // The `)` after a pattern of a `case` doesn't close anything
+                if !self.in_case() {
+                    self.state = self.close();
+                }
+            }
+            (LexerState::Start, '*' | '?' | '[') => {
+                self.push_char(ch);
+                self.state = LexerState::InWildcard;
             }
             (LexerState::Start, _) => {
-                self.current.push(ch);
+                self.push_char(ch);
                 self.state = LexerState::InLiteral;
             }
-            (LexerState::InLiteral, c) if c.is_path_char() => self.current.push(c),
-            (LexerState::InLiteral, '*') => {
-                self.current.push(ch);
+            (LexerState::InLiteral, '>' | '<') if self.current.chars().all(|c| c.is_ascii_digit()) => {
+                // File descriptor number, as in `2>` or `0<`
+                self.push_char(ch);
+                self.state = LexerState::InOperator;
+            }
+            (LexerState::InLiteral, c) if c.is_path_char() || c == '#' => self.push_char(c),
+            (LexerState::InLiteral, '*' | '?' | '[') => {
+                self.push_char(ch);
                 self.state = LexerState::InWildcard;
             }
+            (LexerState::InLiteral | LexerState::InWildcard, '(')
+                if self.current.ends_with(['?', '*', '+', '@', '!']) =>
+            {
+                // An extglob pattern list such as `+(a|b)`
+                self.push_char(ch);
+                self.state = LexerState::InWildcard;
+            }
+            (LexerState::InLiteral, '=') if is_name(&self.current) => {
+                let text = self.take_current();
+                self.tokens.push(Token::Literal(text));
+                self.tokens.push(Token::Symbol(self.this_char(ch)));
+                self.state = LexerState::InAssignment;
+            }
             (LexerState::InLiteral, _) => {
-                self.tokens.push(match self.current.as_str() {
-                    "if" | "then" | "else" | "fi" => {
-                        Token::ControlOperator(take(&mut self.current))
-                    }
-                    _ => Token::Literal(take(&mut self.current)),
-                });
-                self.state = LexerState::Start;
+                self.end_literal(ch.is_metachar());
+                self.state = LexerState::AfterPart;
                 self = self.tokenize_char(ch);
             }
-            (LexerState::InWildcard, c) if c.is_path_char() => self.current.push(c),
+            (LexerState::InWildcard, c)
+                if self.current.matches('(').count() > self.current.matches(')').count() =>
+            {
+                self.push_char(c)
+            }
+            (LexerState::InWildcard, c) if c.is_path_char() || "*?[]!^+@".contains(c) => {
+                self.push_char(c)
+            }
             (LexerState::InWildcard, _) => {
-                self.tokens.push(Token::Wildcard(take(&mut self.current)));
+                let text = self.take_current();
+                self.tokens.push(Token::Wildcard(text));
+                self.state = LexerState::AfterPart;
+                self = self.tokenize_char(ch);
+            }
+            (LexerState::AfterPart, _) if self.operand && self.stack.is_empty() => {
+                self.tokens.push(Token::Join);
+                self.state = LexerState::InOperand;
+                self = self.tokenize_char(ch);
+            }
+            (LexerState::AfterPart | LexerState::InAssignment, c) if c.is_metachar() => {
+                if self.state == LexerState::InAssignment {
+                    // `NAME=` on its own assigns the empty string
+                    self.tokens.push(Token::Literal(Spanned::new("", Span::at(self.pos))));
+                }
                 self.state = LexerState::Start;
                 self = self.tokenize_char(ch);
             }
-            (LexerState::InOperator(io_dir), c) if c == io_dir => {
-                self.current.push(c);
-                self.tokens.push(Token::Symbol(take(&mut self.current)));
+            (LexerState::AfterPart | LexerState::InAssignment, _) => {
+                if self.state == LexerState::AfterPart {
+                    self.tokens.push(Token::Join);
+                }
                 self.state = LexerState::Start;
+                match ch {
+                    // Only starts a comment at the beginning of a word
+                    '#' => {
+                        self.push_char(ch);
+                        self.state = LexerState::InLiteral;
+                    }
+                    _ => self = self.tokenize_char(ch),
+                }
+            }
+            (LexerState::InOperator, c) if is_operator(&format!("{}{c}", self.current)) => {
+                self.push_char(c)
             }
-            (LexerState::InOperator(_), _) => {
-                self.tokens.push(Token::Symbol(take(&mut self.current)));
+            (LexerState::InOperator, _) => {
+                let text = self.take_current();
+                self.tokens.push(Token::Symbol(text));
                 self.state = LexerState::Start;
                 self = self.tokenize_char(ch);
             }
-            (LexerState::InSubstitution, c) if c.is_variable_char() => self.current.push(c),
+            (LexerState::InSubstitution, '{') if self.current.is_empty() => self.push_char(ch),
+            (LexerState::InSubstitution, c) if self.current.starts_with('{') => {
+                self.push_char(c);
+                if braces_closed(&self.current) {
+                    let text = self.take_current();
+                    self.tokens.push(Token::Identifier(text));
+                    self.state = self.close();
+                }
+            }
+            (LexerState::InSubstitution, '\'')
+                if self.current.is_empty() && self.stack.last() == Some(&LexerState::AfterPart) =>
+            {
+                // `$'...'`, whose quote takes the place of the `$`
+                let dollar = self.tokens.pop().and_then(|t| t.span()).unwrap_or_default();
+                self.close();
+                let quote = Spanned::new(ch, Span::new(dollar.start, self.pos.after(ch)));
+                self.tokens.push(Token::Symbol(quote));
+                self.state = LexerState::InAnsiQuote;
+            }
+            (LexerState::InSubstitution, c) if self.current.is_empty() && c.is_special_param() => {
+                self.tokens.push(Token::Identifier(self.this_char(c)));
+                self.state = self.close();
+            }
+            (LexerState::InSubstitution, c) if c.is_variable_char() => self.push_char(c),
             (LexerState::InSubstitution, _) => {
-                self.tokens.push(Token::Identifier(take(&mut self.current)));
-                self.state = self.stack.pop().unwrap_or_default();
+                let text = self.take_current();
+                self.tokens.push(Token::Identifier(text));
+                self.state = self.close();
                 self = self.tokenize_char(ch);
             }
-            (LexerState::InQuote(_), '$') => {
-                self.tokens.push(Token::Str(take(&mut self.current)));
-                self.tokens.push(Token::Symbol('$'.into()));
-                self.stack.push(self.state);
+            (LexerState::InQuote('"') | LexerState::InDocument, '$') => {
+                let text = self.take_current();
+                self.tokens.push(Token::Str(text));
+                self.tokens.push(Token::Symbol(self.this_char(ch)));
+                self.open(self.state);
                 self.state = LexerState::InSubstitution;
             }
-            (LexerState::InQuote(q), c) if c != q => self.current.push(c),
+            (LexerState::InQuote(q), c) if c != q => self.push_char(c),
             (LexerState::InQuote(_), _) => {
-                self.tokens.push(Token::Str(take(&mut self.current)));
-                self.tokens.push(Token::Symbol(ch.into()));
-                self.state = LexerState::Start;
+                let text = self.take_current();
+                self.tokens.push(Token::Str(text));
+                self.tokens.push(Token::Symbol(self.this_char(ch)));
+                self.state = LexerState::AfterPart;
+            }
+            (LexerState::InAnsiQuote, '\'') => {
+                let text = self.take_current();
+                self.tokens.push(Token::Str(decode_ansi_c(text)));
+                self.tokens.push(Token::Symbol(self.this_char(ch)));
+                self.state = LexerState::AfterPart;
+            }
+            (LexerState::InAnsiQuote, c) => self.push_char(c),
+            (LexerState::InArithmetic, c) => {
+                self.push_char(c);
+                if self.current.matches('(').count() == self.current.matches(')').count() {
+                    let expr = self.take_current();
+                    match expr.strip_prefix("((").and_then(|e| e.strip_suffix("))")) {
+                        Some(inner) => self.tokens.extend([
+                            Token::Symbol(expr.slice(&expr[..2])),
+                            Token::Str(expr.slice(inner)),
+                            Token::Symbol(expr.slice(&expr[expr.len() - 2..])),
+                        ]),
+                        None => self.tokens.push(Token::Literal(expr)),
+                    }
+                    self.state = LexerState::Start;
+                }
             }
             (LexerState::InComment, '\n') => {
-                self.tokens.push(Token::Comment(take(&mut self.current)));
+                let text = self.take_current();
+                self.tokens.push(Token::Comment(text));
                 self.state = LexerState::Start;
+                self = self.tokenize_char(ch);
+            }
+            (LexerState::InComment, c) => self.push_char(c),
+            (LexerState::InHereDoc, '\n') => {
+                let line_start = match self.current.is_empty() {
+                    true => self.pos,
+                    false => self.start,
+                };
+                let line = take(&mut self.current);
+                let doc = &mut self.heredocs[0];
+                let line = match doc.strip_tabs {
+                    true => line.trim_start_matches('\t'),
+                    false => &line,
+                };
+
+                if line == doc.delimiter.as_str() {
+                    self.finish_heredoc(line_start);
+                    if self.heredocs.is_empty() {
+                        self.line_start = self.tokens.len();
+                        self.state = LexerState::Start;
+                    }
+                } else {
+                    doc.start.get_or_insert(line_start);
+                    doc.body.push_str(line);
+                    doc.body.push('\n');
+                }
+            }
+            (LexerState::InOperand, '\'' | '"' | '$' | '\\') => {
+                // Ends the text before the quote, substitution or escape
+                if !self.current.is_empty() {
+                    let text = self.take_operand();
+                    self.tokens.extend([text, Token::Join]);
+                }
+                match ch {
+                    '$' => {
+                        self.tokens.push(Token::Symbol(self.this_char(ch)));
+                        self.open(LexerState::AfterPart);
+                        self.state = LexerState::InSubstitution;
+                    }
+                    '\'' | '"' => {
+                        self.tokens.push(Token::Symbol(self.this_char(ch)));
+                        self.state = LexerState::InQuote(ch);
+                    }
+                    // Only read by `escape`, which adds the escaped character
+                    _ => (),
+                }
+            }
+            (LexerState::InHereDoc | LexerState::InDocument | LexerState::InOperand, c) => {
+                self.push_char(c)
             }
-            (LexerState::InComment, c) => self.current.push(c),
         }
         self
     }
+
+    /// Ends the literal being read, which is a keyword if it is a whole word
+    /// at the start of a command.
+    fn end_literal(&mut self, ends_word: bool) {
+        let word = self.take_current();
+        let keyword = ends_word
+            && is_keyword(&word)
+            && (self.at_command_start() || self.after_function_name());
+        match word.as_str() {
+            "case" if keyword => self.cases.push(self.stack.len()),
+            "esac" if keyword => {
+                self.cases.pop();
+            }
+            _ => (),
+        }
+        self.tokens.push(match keyword {
+            true => Token::ControlOperator(word),
+            false => Token::Literal(word),
+        });
+    }
+
+    /// Whether the next word would be the first of a command.
+    fn at_command_start(&self) -> bool {
+        match self.tokens.last() {
+            // The `)` that ends the patterns of a `case` arm
+            Some(Token::Symbol(close)) if close == ")" => self.in_case(),
+            last => starts_command(last),
+        }
+    }
+
+    /// Whether the innermost construct open is a `case`, rather than a
+    /// subshell or substitution inside one.
+    fn in_case(&self) -> bool {
+        self.cases.last() == Some(&self.stack.len())
+    }
+
+    /// Whether a `(` read now opens something: a subshell where a command
+    /// can start, the `()` after the name of a function, or the pattern of
+    /// a `case` arm.
+    fn opens_paren(&self) -> bool {
+        if self.at_command_start() || self.after_function_name() {
+            return true;
+        }
+        match self.tokens.as_slice() {
+            [.., Token::Literal(word)] if self.in_case() && word == "in" => true,
+            [rest @ .., Token::Literal(_)] => starts_command(rest.last()),
+            _ => false,
+        }
+    }
+
+    /// Whether the tokens so far end with `name()` or `function name`, so
+    /// that the next word would start the body of a function.
+    fn after_function_name(&self) -> bool {
+        let (tokens, parens) = match self.tokens.as_slice() {
+            [rest @ .., Token::Symbol(open), Token::Symbol(close)] if open == "(" && close == ")" => {
+                (rest, true)
+            }
+            tokens => (tokens, false),
+        };
+        match tokens {
+            // Without the `()`, only `function` says that a name is one
+            [.., Token::ControlOperator(function), Token::Literal(_)]
+                if !parens && function == "function" =>
+            {
+                true
+            }
+            [.., Token::Literal(_)] => parens,
+            _ => false,
+        }
+    }
+
+    /// Finds the here-documents requested on the current line, in order.
+    fn find_heredocs(&self) -> Vec<HereDoc> {
+        let line = &self.tokens[self.line_start..];
+
+        line.iter()
+            .enumerate()
+            .filter_map(|(i, token)| {
+                let Token::Symbol(op) = token else {
+                    return None;
+                };
+                let strip_tabs = match op.trim_start_matches(|c: char| c.is_ascii_digit()) {
+                    "<<" => false,
+                    "<<-" => true,
+                    _ => return None,
+                };
+                let (delimiter, len) = delimiter(&line[i + 1..])?;
+                Some(HereDoc {
+                    delimiter,
+                    strip_tabs,
+                    index: self.line_start + i + 1 + len,
+                    body: String::new(),
+                    start: None,
+                })
+            })
+            .collect()
+    }
+
+    /// Places the body of the oldest open here-document after its delimiter,
+    /// given where the body ends.
+    fn finish_heredoc(&mut self, end: Position) {
+        let doc = self.heredocs.remove(0);
+        let span = Span::new(doc.start.unwrap_or(end), end);
+        self.tokens.insert(doc.index, Token::Str(Spanned::new(doc.body, span)));
+        for later in self.heredocs.iter_mut() {
+            later.index += 1;
+        }
+    }
+}
+
+/// The delimiter of a here-document, which is the word at the start of
+/// `tokens` with its quotes removed, and how many tokens the word takes.
+fn delimiter(tokens: &[Token<Spanned>]) -> Option<(Spanned, usize)> {
+    let mut delimiter = Spanned::default();
+    let mut len = 0;
+    loop {
+        let (text, taken) = match &tokens[len..] {
+            [Token::Literal(word), ..] => (word, 1),
+            [Token::Symbol(q), Token::Str(word), Token::Symbol(_), ..] if q != "\\" => (word, 3),
+            [Token::Symbol(_), Token::Str(c), ..] => (c, 2),
+            _ => break,
+        };
+        delimiter.append(text);
+        len += taken;
+        match tokens.get(len) {
+            Some(Token::Join) if tokens.len() > len + 1 => len += 1,
+            _ => break,
+        }
+    }
+    (len > 0).then_some((delimiter, len))
+}
+
+/// Whether a command can start after `token`, the last one read.
+fn starts_command(token: Option<&Token<Spanned>>) -> bool {
+    match token {
+        None | Some(Token::ControlOperator(_)) => true,
+        Some(Token::Symbol(sym)) => {
+            matches!(
+                sym.as_str(),
+                ";" | ";;" | ";&" | ";;&" | "\n" | "&" | "&&" | "||" | "|" | "("
+            )
+        }
+        _ => false,
+    }
+}
+
+/// Whether the braces of `${...}` are all closed, leaving out those that are
+/// escaped or quoted.
+fn braces_closed(text: &str) -> bool {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => (),
+            (_, '\\') => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '{') => depth += 1,
+            (None, '}') => depth -= 1,
+            _ => (),
+        }
+    }
+    depth == 0
+}
+
+/// Decodes the escapes of a `$'...'` string, such as `\n`, `\x41` and
+/// `\u263a`. Unknown escapes keep their backslash.
+fn decode_ansi_c(text: Spanned) -> Spanned {
+    let mut decoded = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            decoded.push(c);
+            continue;
+        }
+        let Some(escaped) = chars.next() else {
+            decoded.push(c);
+            break;
+        };
+        let code = match escaped {
+            'a' => Some(0x07),
+            'b' => Some(0x08),
+            'e' | 'E' => Some(0x1b),
+            'f' => Some(0x0c),
+            'n' => Some(0x0a),
+            'r' => Some(0x0d),
+            't' => Some(0x09),
+            'v' => Some(0x0b),
+            '\\' | '\'' | '"' | '?' => Some(escaped as u32),
+            '0'..='7' => digits(&mut chars, 8, 2, escaped.to_digit(8)).map(|n| n & 0xff),
+            'x' => digits(&mut chars, 16, 2, None),
+            'u' => digits(&mut chars, 16, 4, None),
+            'U' => digits(&mut chars, 16, 8, None),
+            'c' => chars.next().map(|c| c as u32 & 0x1f),
+            _ => None,
+        };
+        match code {
+            Some(code) => decoded.extend(char::from_u32(code)),
+            None => {
+                decoded.push(c);
+                decoded.push(escaped);
+            }
+        }
+    }
+    Spanned {
+        text: decoded,
+        span: text.span,
+    }
+}
+
+/// Reads up to `max` digits in `radix` after those making up `value`, if
+/// there are any.
+fn digits(chars: &mut Peekable<Chars>, radix: u32, max: usize, value: Option<u32>) -> Option<u32> {
+    let mut value = value;
+    for _ in 0..max {
+        let Some(digit) = chars.peek().and_then(|c| c.to_digit(radix)) else {
+            break;
+        };
+        chars.next();
+        value = Some(value.unwrap_or(0).saturating_mul(radix) + digit);
+    }
+    value
+}
+
+/// Whether `op` is a complete operator, optionally prefixed by a file descriptor.
+fn is_operator(op: &str) -> bool {
+    matches!(
+        op.trim_start_matches(|c: char| c.is_ascii_digit()),
+        "<" | ">" | "<<" | "<<-" | "<<<" | ">>" | "<&" | ">&" | "&" | "&>" | "&>>"
+            | "|" | "||" | "&&" | ";" | ";;" | ";&" | ";;&"
+    )
+}
+
+/// Words that are reserved when they start a command.
+const KEYWORDS: [&str; 15] = [
+    "if", "then", "elif", "else", "fi", "while", "until", "for", "do", "done", "case", "esac",
+    "function", "{", "}",
+];
+
+/// Whether `word` is a reserved word, such as `if`, when it starts a command.
+fn is_keyword(word: &str) -> bool {
+    KEYWORDS.contains(&word)
+}
+
+/// Whether `word` can be the name of a variable.
+pub fn is_name(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_variable_char())
 }
 
 trait Valid {
-    fn is_variable_char(self) -> bool;
-    fn is_path_char(self) -> bool;
+    fn is_variable_char(&self) -> bool;
+    fn is_path_char(&self) -> bool;
+    fn is_metachar(&self) -> bool;
+    fn is_special_param(&self) -> bool;
 }
 
 impl Valid for char {
-    fn is_variable_char(self) -> bool {
-        self.is_alphanumeric() || ['_', '{', '}'].contains(&self)
+    fn is_variable_char(&self) -> bool {
+        self.is_alphanumeric() || *self == '_'
+    }
+
+    fn is_path_char(&self) -> bool {
+        self.is_alphanumeric() || ['_', '~', '/', '.', '-', '{', '}', ','].contains(self)
+    }
+
+    /// A parameter named by this one character, such as `$?` or `$1`.
+    fn is_special_param(&self) -> bool {
+        self.is_ascii_digit() || ['?', '$', '!', '#', '@', '*'].contains(self)
     }
 
-    fn is_path_char(self) -> bool {
-        self.is_alphanumeric() || ['_', '~', '/', '.', '-'].contains(&self)
+    /// Ends a word when unquoted.
+    fn is_metachar(&self) -> bool {
+        self.is_whitespace() || ['|', '&', ';', '<', '>', '(', ')'].contains(self)
     }
 }
@@ -1,2 +1,10 @@
+pub mod arith;
+pub mod brace;
+pub mod builtins;
+pub mod env;
+pub mod exec;
+pub mod expand;
+pub mod glob;
 pub mod lexer;
 pub mod parser;
+pub mod span;
@@ -1,43 +1,93 @@
 use std::error::Error;
-
-
-// use std::io::{stdin, stdout, Write};
-
-// const PROMPT: &'static str = "rush> ";
+use std::io::{stdin, stdout, Write};
+use std::process;
 
 use lang::{
-    lexer::Tokenize,
-    parser::Parse
+    exec::{Flow, Shell},
+    lexer::{Token, Tokenize},
+    parser::{Parse, Tree},
+    span::Spanned,
 };
 
-fn main() -> Result<(), Box<dyn Error>> {
-    // let mut line = String::new();
-    
-    // print!("{PROMPT}");
-    // stdout().flush()?;
-    
-    // stdin().read_line(&mut line)?;
-    
-    
-    // println!("{line}");
-
-    println!("{}\n", r#"~/bin/ansi_colors"#.tokenize().parse());
-    println!("{}\n", r#"ls -F --group-directories-first"#.tokenize().parse());
-    // println!("{}\n", r#"cat << EOF > file | wc -c | tr -d " " > file2"#.tokenize().parse());
-    println!("{}\n", r#"xclip -selection c -o"#.tokenize().parse());
-    // println!("{}\n", r#"grep ":Zone.Identifier""#.tokenize().parse());
-    // println!("{}\n", r#"MY_VAR="this is the value of my variable""#.tokenize().parse());
-    println!("{}\n", r#"alias colors='~/bin/ansi_colors'"#.tokenize().parse());
-    println!("{}\n", r#"history | grep git | xargs rm"#.tokenize().parse());
-    // println!("{}\n", r#"ls ./src/*.rs | xargs basename -s .rs"#.tokenize().parse());
-    println!("{}\n", r#"echo $(ls -a)"#.tokenize().parse());
-    println!("{}\n", r#"ls $(echo -a) -l"#.tokenize().parse());
-    println!("{}\n", r#"find . -type f | grep ":Zone.Identifier" | xargs rm"#.tokenize().parse());
-    println!("{}\n", r#"echo -e $(ls -a) "Directory" $(history | grep git | sort -u -k2)"#.tokenize().parse());
-
-
-    Ok(())
+const PROMPT: &str = "rush> ";
+/// The prompt for the lines of a command that goes on past the first, when
+/// `PS2` isn't set.
+const PS2: &str = "> ";
+
+/// The input read so far, parsed.
+enum Input {
+    /// Nothing to run, such as a blank line or a comment
+    Empty,
+    Complete(Tree<Spanned>),
+    /// A command that ends on a later line, with the error to report if the
+    /// input ends first
+    Incomplete(String),
+    /// A command that can't be run however it goes on
+    Invalid(String),
 }
 
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut shell = Shell::new();
+    let mut input = String::new();
 
+    loop {
+        let prompt = match input.is_empty() {
+            true => PROMPT,
+            false => shell.env.get("PS2").unwrap_or(PS2),
+        };
+        print!("{prompt}");
+        stdout().flush()?;
 
+        if stdin().read_line(&mut input)? == 0 {
+            // EOF (Ctrl-D), which may come before the command is complete
+            if let Input::Incomplete(e) = parse(&input) {
+                shell.io.error(e);
+                shell.status = 2;
+            }
+            println!();
+            process::exit(shell.status);
+        }
+
+        let tree = match parse(&input) {
+            Input::Complete(tree) => tree,
+            // Keep reading lines until the command is complete
+            Input::Incomplete(_) => continue,
+            Input::Empty => {
+                input.clear();
+                continue;
+            }
+            Input::Invalid(e) => {
+                // A command that doesn't parse is reported and skipped, like bash
+                shell.io.error(e);
+                shell.status = 2;
+                input.clear();
+                continue;
+            }
+        };
+        input.clear();
+
+        shell.execute(&tree);
+        if let Some(Flow::Exit(status)) = shell.flow {
+            process::exit(status);
+        }
+    }
+}
+
+/// Parses `source`, telling input that more lines could complete apart from
+/// input that is already wrong.
+fn parse(source: &str) -> Input {
+    let mut tokens = match source.try_tokenize() {
+        Ok(tokens) => tokens,
+        // The lexer only fails on quotes and substitutions left open
+        Err(e) => return Input::Incomplete(e.render(source)),
+    };
+    tokens.retain(|t| !matches!(t, Token::Comment(_)));
+    if tokens.is_empty() {
+        return Input::Empty;
+    }
+    match tokens.parse() {
+        Ok(tree) => Input::Complete(tree),
+        Err(e) if e.is_incomplete() => Input::Incomplete(e.render(source)),
+        Err(e) => Input::Invalid(e.render(source)),
+    }
+}
@@ -7,25 +7,55 @@
  * Wildcard
  * Str
  * Comment
+ * Join
  *
  * =========== EBNF ===========
  * My shell's grammar, BNF
  *
- * <pipeline> ::= <command> { `|` <command> }
- * <command>  ::= ( <literal> | <substitute> ) <args>
- * <args> ::= { <literal> | <quote> | <substitute> }
+ * <list> ::= { <newline> } [ <and-or> { <separator> <and-or> } [ <separator> ] ]
+ * <separator> ::= ( `;` | `&` | <newline> ) { <newline> }
+ * <and-or> ::= <pipeline> { ( `&&` | `||` ) { <newline> } <pipeline> }
+ * <pipeline> ::= <command> { `|` { <newline> } <command> }
+ * <command>  ::= <compound> { <redirect> }
+ *              | <function>
+ *              | { <assign> | <redirect> } [ <word> <args> ]
+ * <compound> ::= <if> | <while> | <for> | <case> | <group>
+ * <function> ::= ( <name> `(` `)` | `function` <name> [ `(` `)` ] ) { <newline> } <compound> { <redirect> }
+ * <group> ::= `{` <list> `}` | `(` <list> `)`
+ * <if> ::= `if` <list> `then` <list> { `elif` <list> `then` <list> } [ `else` <list> ] `fi`
+ * <while> ::= ( `while` | `until` ) <list> <do>
+ * <for> ::= `for` <name> [ { <newline> } `in` { <word> } ] [ <separator> ] <do>
+ *         | `for` `((` <string> `))` [ <separator> ] <do>
+ * <do> ::= `do` <list> `done`
+ * <case> ::= `case` <word> { <newline> } `in` { <newline> } { <case-arm> } `esac`
+ * <case-arm> ::= [ `(` ] <word> { `|` <word> } `)` <list> [ <case-end> { <newline> } ]
+ * <case-end> ::= `;;` | `;&` | `;;&`
+ * <assign> ::= <literal> `=` <part> { <join> <part> }
+ * <args> ::= { <word> | <redirect> }
+ * <word> ::= <part> { ( <join> | `=` ) <part> }
+ * <part> ::= <literal> | <wildcard> | <quote> | <substitute> | `\` <string>
+ * <redirect> ::= [<fd>] ( `<` | `>` | `>>` | `<&` | `>&` | `<<<` ) <word>
+ *              | [<fd>] ( `<<` | `<<-` ) <word> [<string>]
+ *              | ( `&>` | `&>>` ) <word>
  *
  * <quote> ::= `"` <string> { <substitute> <string> } `"`
  * <substitute> ::= `$` (<ident> | <subshell>)
- * <subshell> ::= `(` <pipeline> `)`
+ * <ident> ::= <name> | `{` [`#`] <name> [ <param-op> ] `}`
+ * <subshell> ::= `(` <list> `)`
  *
  *
  *
- * <string> == Str
+ * <string> == Str, which after a here-document's delimiter is its body
  * <ident> == Identifier
  * <literal> == Literal
+ * <wildcard> == Wildcard
+ * <join> == Join, between parts of a word written without spaces
+ * <fd> == a decimal file descriptor, lexed as part of the operator
+ * <newline> == the Symbol `\n`, ending a line of input
+ * `if`, `then`, ... == ControlOperator, a reserved word starting a command
 */
-use crate::lexer::Token;
+use crate::lexer::{document_from, is_name, operand_from, Token};
+use crate::span::{join_spans, render, Position, Span, Spanned};
 
 use std::default::Default;
 use std::iter::{FromIterator, Peekable};
@@ -36,154 +66,1295 @@ pub enum Tree<T>
 where
     T: AsRef<str> + Clone,
 {
+    /// Commands run one after the other, separated by `;`, `&` or newlines
+    List(Vec<Tree<T>>),
+    /// `left && right`, running `right` only if `left` succeeds
+    And(Box<Tree<T>>, Box<Tree<T>>),
+    /// `left || right`, running `right` only if `left` fails
+    Or(Box<Tree<T>>, Box<Tree<T>>),
+    /// `tree &`, started without waiting for it to finish
+    Background(Box<Tree<T>>),
     Pipe(Box<Tree<T>>, Box<Tree<T>>),
+    /// `if`, running the body of the first branch whose condition succeeds,
+    /// or `otherwise` when none does
+    If {
+        branches: Vec<(Tree<T>, Tree<T>)>,
+        otherwise: Option<Box<Tree<T>>>,
+    },
+    /// `while condition; do body; done`, or `until` when `until` is set
+    While {
+        condition: Box<Tree<T>>,
+        body: Box<Tree<T>>,
+        until: bool,
+    },
+    /// `for name in words; do body; done`, or over the positional parameters
+    /// when there is no `in`
+    For {
+        name: T,
+        words: Option<Vec<Tree<T>>>,
+        body: Box<Tree<T>>,
+    },
+    /// `case word in pattern) body;; ... esac`
+    Case {
+        word: Box<Tree<T>>,
+        arms: Vec<CaseArm<T>>,
+    },
+    /// `name() { body; }` or `function name { body; }`, defining a command
+    FunctionDef {
+        name: T,
+        body: Box<Tree<T>>,
+    },
+    /// `{ body; }`, run by the shell itself, or `( body )` when `subshell` is
+    /// set, run by a forked child so that its changes don't outlast it
+    Group {
+        body: Box<Tree<T>>,
+        subshell: bool,
+    },
+    /// `for ((init; condition; step)); do body; done`, whose expressions are
+    /// expanded like double quoted strings before being evaluated
+    ArithFor {
+        init: Box<Tree<T>>,
+        condition: Box<Tree<T>>,
+        step: Box<Tree<T>>,
+        body: Box<Tree<T>>,
+    },
     Command {
         name: Box<Tree<T>>,
         args: Vec<Tree<T>>,
     },
 
+    Redirect {
+        tree: Box<Tree<T>>,
+        redirects: Vec<Redirection<T>>,
+    },
+    /// `NAME=value ...`, setting shell variables, or setting environment
+    /// variables for just `command` when there is one
+    Assign {
+        vars: Vec<(T, Tree<T>)>,
+        command: Option<Box<Tree<T>>>,
+    },
+
     Quote(char, Vec<Tree<T>>),
     Subshell(Box<Tree<T>>),
+    /// `${name<op>args}`, where the arguments depend on the operator
+    Parameter {
+        name: T,
+        op: ParamOp,
+        args: Vec<Tree<T>>,
+    },
+    /// Parts written next to each other that form a single word, as in
+    /// `$HOME/bin` or `NAME="value"`
+    Word(Vec<Tree<T>>),
 
     Literal(T),
+    /// A word with unquoted `*`, `?` or `[`, matched against file names
+    Glob(T),
     Identifier(T),
     String(T),
 }
 
+/// An I/O redirection applied to the file descriptor `fd`.
+#[derive(Clone)]
+pub struct Redirection<T>
+where
+    T: AsRef<str> + Clone,
+{
+    pub fd: u32,
+    pub op: RedirectOp,
+    pub target: Tree<T>,
+}
+
+/// Why a line couldn't be parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    /// A token that can't appear where it was found
+    UnexpectedToken(Token<Spanned>),
+    /// The input ended in the middle of a command
+    UnexpectedEof,
+    /// The opening quote of a string that is never closed
+    UnbalancedQuote(Spanned),
+    /// A `(` that is never closed
+    UnbalancedParen(Spanned),
+    /// A `$` that isn't followed by something to substitute, or a `${...}`
+    /// that can't be expanded
+    InvalidSubstitution(Spanned),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedToken(token) => write!(
+                f,
+                "syntax error near unexpected token `{}'",
+                match token.inner() {
+                    "\n" => "newline",
+                    inner => inner,
+                }
+            ),
+            Self::UnexpectedEof => write!(f, "syntax error: unexpected end of file"),
+            Self::UnbalancedQuote(q) => write!(f, "unexpected EOF while looking for matching `{q}'"),
+            Self::UnbalancedParen(_) => write!(f, "unexpected EOF while looking for matching `)'"),
+            Self::InvalidSubstitution(text) => write!(f, "{text}: bad substitution"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl ParseError {
+    /// Whether the input ended before the command did, as after `|` or
+    /// inside an `if`, so that more input could still complete it.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            Self::UnexpectedEof | Self::UnbalancedQuote(_) | Self::UnbalancedParen(_)
+        )
+    }
+
+    /// Where in the source the error was found, when it is known.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::UnexpectedToken(token) => token.span(),
+            Self::UnexpectedEof => None,
+            Self::UnbalancedQuote(text)
+            | Self::UnbalancedParen(text)
+            | Self::InvalidSubstitution(text) => text.span,
+        }
+    }
+
+    /// The error's message, followed by the line of `source` it was found on
+    /// with the offending text underlined. Errors without a span point at the
+    /// end of the input.
+    pub fn render(&self, source: &str) -> String {
+        let span = self
+            .span()
+            .unwrap_or_else(|| Span::at(Position::default().after_str(source.trim_end())));
+        render(source, span, self)
+    }
+}
+
+/// The error for `token`, found where something else was expected.
+fn unexpected(token: Option<Token<Spanned>>) -> ParseError {
+    match token {
+        Some(token) => ParseError::UnexpectedToken(token),
+        None => ParseError::UnexpectedEof,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RedirectOp {
+    /// `[n]<word`
+    Read,
+    /// `[n]>word`
+    Write,
+    /// `[n]>>word`
+    Append,
+    /// `[n]<&word` or `[n]>&word`, making `n` a copy of the descriptor `word`
+    Duplicate,
+    /// `&>word`, writing both stdout and stderr
+    WriteAll,
+    /// `&>>word`
+    AppendAll,
+    /// `[n]<<word` or `[n]<<-word`, whose target is the document's body
+    HereDoc,
+    /// `[n]<<<word`
+    HereString,
+}
+
+/// A branch of a `case`, run when the word matches any of its patterns.
+#[derive(Clone)]
+pub struct CaseArm<T>
+where
+    T: AsRef<str> + Clone,
+{
+    pub patterns: Vec<Tree<T>>,
+    pub body: Tree<T>,
+    pub end: CaseEnd,
+}
+
+/// What happens after the body of a `case` arm runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CaseEnd {
+    /// `;;`, ending the `case`
+    Break,
+    /// `;&`, running the next arm's body as well
+    FallThrough,
+    /// `;;&`, going on to test the patterns of the next arms
+    Continue,
+}
+
+/// The operators of a parameter expansion such as `${name:-word}`.
+///
+/// Operators that check whether the variable is set also treat an empty
+/// value as unset when `null` is true, which is written with a colon.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParamOp {
+    /// `${#name}`, the length of the value
+    Length,
+    /// `${name:-word}`, `word` if the variable is unset
+    Default { null: bool },
+    /// `${name:=word}`, which also sets the variable to `word`
+    Assign { null: bool },
+    /// `${name:?word}`, failing with the message `word`
+    Error { null: bool },
+    /// `${name:+word}`, `word` only if the variable is set
+    Alternate { null: bool },
+    /// `${name#pattern}` or `${name##pattern}`
+    RemovePrefix { longest: bool },
+    /// `${name%pattern}` or `${name%%pattern}`
+    RemoveSuffix { longest: bool },
+    /// `${name/pattern/string}`, or `${name//pattern/string}` for every match
+    Replace { all: bool },
+    /// `${name:offset}` or `${name:offset:length}`
+    Substring,
+}
+
+impl RedirectOp {
+    /// Splits a lexed operator such as `2>>` into its descriptor and operation.
+    pub fn parse(sym: &str) -> Option<(u32, RedirectOp)> {
+        let op = sym.trim_start_matches(|c: char| c.is_ascii_digit());
+        let fd = sym[..sym.len() - op.len()].parse().ok();
+
+        let (default, op) = match op {
+            "<" => (0, RedirectOp::Read),
+            ">" => (1, RedirectOp::Write),
+            ">>" => (1, RedirectOp::Append),
+            "<&" => (0, RedirectOp::Duplicate),
+            ">&" => (1, RedirectOp::Duplicate),
+            "<<" | "<<-" => (0, RedirectOp::HereDoc),
+            "<<<" => (0, RedirectOp::HereString),
+            "&>" if fd.is_none() => (1, RedirectOp::WriteAll),
+            "&>>" if fd.is_none() => (1, RedirectOp::AppendAll),
+            _ => return None,
+        };
+        Some((fd.unwrap_or(default), op))
+    }
+}
+
 trait TreeBuilder {
-    fn parse_pipe(&mut self) -> Tree<String>;
-    fn parse_command(&mut self) -> Tree<String>;
-    fn parse_substitute(&mut self) -> Tree<String>;
-    fn parse_subshell(&mut self) -> Tree<String>;
-    fn parse_quote(&mut self, q: char) -> Tree<String>;
+    fn parse_list(&mut self) -> Result<Tree<Spanned>, ParseError>;
+    fn parse_compound_list(&mut self) -> Result<Tree<Spanned>, ParseError>;
+    fn parse_and_or(&mut self) -> Result<Tree<Spanned>, ParseError>;
+    fn parse_pipe(&mut self) -> Result<Tree<Spanned>, ParseError>;
+    fn parse_command(&mut self) -> Result<Tree<Spanned>, ParseError>;
+    fn parse_compound(&mut self) -> Result<Tree<Spanned>, ParseError>;
+    fn parse_if(&mut self) -> Result<Tree<Spanned>, ParseError>;
+    fn parse_while(&mut self, until: bool) -> Result<Tree<Spanned>, ParseError>;
+    fn parse_for(&mut self) -> Result<Tree<Spanned>, ParseError>;
+    fn parse_do(&mut self) -> Result<Tree<Spanned>, ParseError>;
+    fn parse_case(&mut self) -> Result<Tree<Spanned>, ParseError>;
+    fn parse_function(&mut self, name: Spanned) -> Result<Tree<Spanned>, ParseError>;
+    fn parse_group(&mut self, open: Spanned) -> Result<Tree<Spanned>, ParseError>;
+    fn parse_word(&mut self) -> Result<Option<Tree<Spanned>>, ParseError>;
+    fn parse_part(&mut self) -> Result<Option<Tree<Spanned>>, ParseError>;
+    fn parse_redirect(&mut self) -> Result<Option<Redirection<Spanned>>, ParseError>;
+    fn parse_substitute(&mut self, dollar: Spanned) -> Result<Tree<Spanned>, ParseError>;
+    fn parse_subshell(&mut self) -> Result<Tree<Spanned>, ParseError>;
+    fn parse_quote(&mut self, open: Spanned) -> Result<Tree<Spanned>, ParseError>;
+    fn next_symbol(&mut self, sym: &str) -> bool;
+    fn next_keyword(&mut self, word: &str) -> bool;
+    fn next_literal(&mut self, word: &str) -> bool;
+    fn skip_newlines(&mut self);
 }
 
-impl FromIterator<Token<String>> for Tree<String> {
+impl FromIterator<Token<Spanned>> for Result<Tree<Spanned>, ParseError> {
     fn from_iter<I>(iter: I) -> Self
     where
-        I: IntoIterator<Item = Token<String>>,
+        I: IntoIterator<Item = Token<Spanned>>,
     {
-        iter.into_iter().peekable().parse_pipe()
+        let mut tokens = iter
+            .into_iter()
+            .filter(|t| !matches!(t, Token::Comment(_)))
+            .peekable();
+        let tree = tokens.parse_list()?;
+        // The list only ends early at a token that can't start a command
+        match tokens.next() {
+            Some(token) => Err(ParseError::UnexpectedToken(token)),
+            None => Ok(tree),
+        }
     }
 }
 
 impl<I> TreeBuilder for Peekable<I>
 where
-    I: Iterator<Item = Token<String>>,
+    I: Iterator<Item = Token<Spanned>>,
 {
-    fn parse_pipe(&mut self) -> Tree<String> {
-        let mut tree = self.parse_command();
+    fn parse_list(&mut self) -> Result<Tree<Spanned>, ParseError> {
+        let mut list = vec![];
+        loop {
+            self.skip_newlines();
+            // The list ends with the input, the `)` of a subshell, the end
+            // of a `case` arm, or a keyword such as `then` or `fi`
+            match self.peek() {
+                None => break,
+                Some(Token::Symbol(sym)) if matches!(sym.as_str(), ")" | ";;" | ";&" | ";;&") => {
+                    break
+                }
+                Some(Token::ControlOperator(word)) if !starts_compound(word) => break,
+                _ => (),
+            }
 
-        while let Some(_) = self.next_if(|t| t.inner() == "|") {
-            tree = Tree::Pipe(Box::new(tree), Box::new(self.parse_command()));
+            let tree = self.parse_and_or()?;
+            if self.next_symbol("&") {
+                list.push(Tree::Background(Box::new(tree)));
+            } else if self.next_symbol(";") || self.next_symbol("\n") {
+                list.push(tree);
+            } else {
+                list.push(tree);
+                break;
+            }
         }
-        tree
+
+        Ok(match list.len() {
+            1 => list.remove(0),
+            _ => Tree::List(list),
+        })
     }
 
-    fn parse_command(&mut self) -> Tree<String> {
-        Tree::Command {
-            name: match self.next().expect("INVALID INPUT") {
-                Token::Literal(mut lit) => Box::new(Tree::Literal(mem::take(&mut lit))),
-                Token::Symbol(sym) if sym.as_str() == "$" => Box::new(self.parse_substitute()),
-                _ => todo!("Implement error handling for invalid command tokens"),
+    /// Parses a list that must have a command in it, such as the condition
+    /// or the body of a loop.
+    fn parse_compound_list(&mut self) -> Result<Tree<Spanned>, ParseError> {
+        match self.parse_list()? {
+            Tree::List(list) if list.is_empty() => Err(unexpected(self.next())),
+            list => Ok(list),
+        }
+    }
+
+    fn parse_and_or(&mut self) -> Result<Tree<Spanned>, ParseError> {
+        let mut tree = self.parse_pipe()?;
+        loop {
+            if self.next_symbol("&&") {
+                self.skip_newlines();
+                tree = Tree::And(Box::new(tree), Box::new(self.parse_pipe()?));
+            } else if self.next_symbol("||") {
+                self.skip_newlines();
+                tree = Tree::Or(Box::new(tree), Box::new(self.parse_pipe()?));
+            } else {
+                return Ok(tree);
+            }
+        }
+    }
+
+    fn parse_pipe(&mut self) -> Result<Tree<Spanned>, ParseError> {
+        let mut tree = self.parse_command()?;
+
+        while self.next_symbol("|") {
+            self.skip_newlines();
+            tree = Tree::Pipe(Box::new(tree), Box::new(self.parse_command()?));
+        }
+        Ok(tree)
+    }
+
+    fn parse_command(&mut self) -> Result<Tree<Spanned>, ParseError> {
+        let compound = match self.peek() {
+            Some(Token::ControlOperator(_)) => true,
+            Some(Token::Symbol(sym)) => sym.as_str() == "(",
+            _ => false,
+        };
+        if compound {
+            let tree = self.parse_compound()?;
+            let mut redirects = vec![];
+            while let Some(redirect) = self.parse_redirect()? {
+                redirects.push(redirect);
+            }
+            return Ok(match redirects.is_empty() {
+                true => tree,
+                false => Tree::Redirect {
+                    tree: Box::new(tree),
+                    redirects,
+                },
+            });
+        }
+
+        let mut vars = vec![];
+        let mut redirects = vec![];
+        let name = loop {
+            if let Some(redirect) = self.parse_redirect()? {
+                redirects.push(redirect);
+                continue;
+            }
+            match self.parse_word()?.map(split_assignment) {
+                Some(Ok(var)) => vars.push(var),
+                Some(Err(word)) => break Box::new(word),
+                None if !vars.is_empty() => {
+                    let assign = Tree::Assign { vars, command: None };
+                    return Ok(match redirects.is_empty() {
+                        true => assign,
+                        false => Tree::Redirect {
+                            tree: Box::new(assign),
+                            redirects,
+                        },
+                    });
+                }
+                None => return Err(unexpected(self.next())),
+            }
+        };
+
+        // `name()` starts a function definition
+        if vars.is_empty() && redirects.is_empty() && self.next_symbol("(") {
+            if !self.next_symbol(")") {
+                return Err(unexpected(self.next()));
+            }
+            let Tree::Literal(name) = *name else {
+                return Err(ParseError::UnexpectedToken(Token::Symbol("(".into())));
+            };
+            return self.parse_function(name);
+        }
+
+        let mut args: Vec<Tree<Spanned>> = vec![];
+        loop {
+            if let Some(redirect) = self.parse_redirect()? {
+                redirects.push(redirect);
+            } else if let Some(arg) = self.parse_word()? {
+                args.push(arg);
+            } else {
+                break;
+            }
+        }
+
+        let mut command = Tree::Command { name, args };
+        if !redirects.is_empty() {
+            command = Tree::Redirect {
+                tree: Box::new(command),
+                redirects,
+            };
+        }
+        Ok(match vars.is_empty() {
+            true => command,
+            false => Tree::Assign {
+                vars,
+                command: Some(Box::new(command)),
             },
-            args: {
-                let mut args: Vec<Tree<String>> = vec![];
+        })
+    }
 
-                while let Some(token) = self.peek() {
-                    match token {
-                        Token::Literal(_) => {
-                            args.push(Tree::Literal(mem::take(self.next().unwrap().inner_mut())))
-                        }
-                        Token::Symbol(sym) if sym.as_str() == "$" => {
-                            self.next();
-                            args.push(self.parse_substitute());
-                        }
-                        Token::Symbol(sym) if sym.as_str() == "\"" => {
-                            self.next();
-                            args.push(self.parse_quote('\"'));
+    fn parse_compound(&mut self) -> Result<Tree<Spanned>, ParseError> {
+        match self.next() {
+            Some(Token::ControlOperator(word)) => match word.as_str() {
+                "if" => self.parse_if(),
+                "while" => self.parse_while(false),
+                "until" => self.parse_while(true),
+                "for" => self.parse_for(),
+                "case" => self.parse_case(),
+                "{" => self.parse_group(word),
+                "function" => match self.next() {
+                    Some(Token::Literal(name)) => {
+                        if self.next_symbol("(") && !self.next_symbol(")") {
+                            return Err(unexpected(self.next()));
                         }
-                        Token::Symbol(sym) if sym.as_str() == "\'" => {
-                            self.next();
-                            args.push(self.parse_quote('\''))
-                        }
-                        _ => break,
-                        // Token::Wildcard(_) => unimplemented!(),
-                        // Token::ControlOperator(_) => unimplemented!(),
+                        self.parse_function(name)
                     }
-                }
-                args
+                    token => Err(unexpected(token)),
+                },
+                _ => Err(ParseError::UnexpectedToken(Token::ControlOperator(word))),
             },
+            Some(Token::Symbol(sym)) if sym.as_str() == "(" => self.parse_group(sym),
+            token => Err(unexpected(token)),
+        }
+    }
+
+    fn parse_if(&mut self) -> Result<Tree<Spanned>, ParseError> {
+        let mut branches = vec![];
+        loop {
+            let condition = self.parse_compound_list()?;
+            if !self.next_keyword("then") {
+                return Err(unexpected(self.next()));
+            }
+            branches.push((condition, self.parse_compound_list()?));
+            if !self.next_keyword("elif") {
+                break;
+            }
+        }
+
+        let otherwise = match self.next_keyword("else") {
+            true => Some(Box::new(self.parse_compound_list()?)),
+            false => None,
+        };
+        if !self.next_keyword("fi") {
+            return Err(unexpected(self.next()));
+        }
+        Ok(Tree::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    fn parse_while(&mut self, until: bool) -> Result<Tree<Spanned>, ParseError> {
+        let condition = self.parse_compound_list()?;
+        Ok(Tree::While {
+            condition: Box::new(condition),
+            body: Box::new(self.parse_do()?),
+            until,
+        })
+    }
+
+    fn parse_for(&mut self) -> Result<Tree<Spanned>, ParseError> {
+        if let Some(Token::Symbol(open)) =
+            self.next_if(|t| matches!(t, Token::Symbol(s) if s.as_str() == "(("))
+        {
+            let Some(Token::Str(exprs)) = self.next() else {
+                return Err(ParseError::UnbalancedParen(open));
+            };
+            if !self.next_symbol("))") {
+                return Err(ParseError::UnbalancedParen(open));
+            }
+            let [init, condition, step] = exprs.split(';').collect::<Vec<_>>()[..] else {
+                return Err(ParseError::UnexpectedToken(Token::Str(exprs)));
+            };
+            self.next_symbol(";");
+            self.skip_newlines();
+            return Ok(Tree::ArithFor {
+                init: Box::new(parse_document(&exprs.slice(init))?),
+                condition: Box::new(parse_document(&exprs.slice(condition))?),
+                step: Box::new(parse_document(&exprs.slice(step))?),
+                body: Box::new(self.parse_do()?),
+            });
+        }
+
+        let name = match self.next() {
+            Some(Token::Literal(name)) if is_name(&name) => name,
+            token => return Err(unexpected(token)),
+        };
+        self.skip_newlines();
+        let words = match self.next_literal("in") {
+            true => {
+                let mut words = vec![];
+                while let Some(word) = self.parse_word()? {
+                    words.push(word);
+                }
+                Some(words)
+            }
+            false => None,
+        };
+        self.next_symbol(";");
+        self.skip_newlines();
+        Ok(Tree::For {
+            name,
+            words,
+            body: Box::new(self.parse_do()?),
+        })
+    }
+
+    fn parse_do(&mut self) -> Result<Tree<Spanned>, ParseError> {
+        if !self.next_keyword("do") {
+            return Err(unexpected(self.next()));
+        }
+        let body = self.parse_compound_list()?;
+        if !self.next_keyword("done") {
+            return Err(unexpected(self.next()));
+        }
+        Ok(body)
+    }
+
+    fn parse_case(&mut self) -> Result<Tree<Spanned>, ParseError> {
+        let Some(word) = self.parse_word()? else {
+            return Err(unexpected(self.next()));
+        };
+        self.skip_newlines();
+        if !self.next_literal("in") {
+            return Err(unexpected(self.next()));
+        }
+
+        let mut arms = vec![];
+        loop {
+            self.skip_newlines();
+            // Right after `in`, `esac` isn't at the start of a command
+            if self.next_keyword("esac") || self.next_literal("esac") {
+                break;
+            }
+
+            self.next_symbol("(");
+            let mut patterns = vec![];
+            loop {
+                match self.parse_word()? {
+                    Some(pattern) => patterns.push(pattern),
+                    None => return Err(unexpected(self.next())),
+                }
+                if !self.next_symbol("|") {
+                    break;
+                }
+            }
+            if !self.next_symbol(")") {
+                return Err(unexpected(self.next()));
+            }
+            let body = self.parse_list()?;
+            let end = if self.next_symbol(";&") {
+                CaseEnd::FallThrough
+            } else if self.next_symbol(";;&") {
+                CaseEnd::Continue
+            } else {
+                // The last arm doesn't need its `;;`
+                self.next_symbol(";;");
+                CaseEnd::Break
+            };
+            arms.push(CaseArm {
+                patterns,
+                body,
+                end,
+            });
         }
+        Ok(Tree::Case {
+            word: Box::new(word),
+            arms,
+        })
     }
 
-    fn parse_substitute(&mut self) -> Tree<String> {
-        let token = self.next().expect("INVALID INPUT");
+    fn parse_function(&mut self, name: Spanned) -> Result<Tree<Spanned>, ParseError> {
+        self.skip_newlines();
+        // The body is a compound command, along with its redirections
+        let body = match self.peek() {
+            Some(Token::ControlOperator(word)) if starts_compound(word) => self.parse_command()?,
+            Some(Token::Symbol(sym)) if sym.as_str() == "(" => self.parse_command()?,
+            _ => return Err(unexpected(self.next())),
+        };
+        Ok(Tree::FunctionDef {
+            name,
+            body: Box::new(body),
+        })
+    }
 
-        match token {
-            Token::Identifier(mut id) => Tree::Identifier(mem::take(&mut id)),
-            Token::Symbol(sym) if sym.as_str() == "(" => {
-                let subs = self.parse_subshell();
+    fn parse_group(&mut self, open: Spanned) -> Result<Tree<Spanned>, ParseError> {
+        let subshell = open.as_str() == "(";
+        let body = self.parse_list()?;
+        let closed = match subshell {
+            true => self.next_symbol(")"),
+            false => self.next_keyword("}"),
+        };
+        match self.peek() {
+            _ if closed => (),
+            None if subshell => return Err(ParseError::UnbalancedParen(open)),
+            _ => return Err(unexpected(self.next())),
+        }
+        Ok(Tree::Group {
+            body: Box::new(body),
+            subshell,
+        })
+    }
 
-                self.peek()
-                    .and_then(|t| (t.inner() == ")").then_some(()))
-                    .expect("Unbalanced parenthesis!"); // eventually ok_or(...)?
-                self.next();
-                subs
+    fn parse_word(&mut self) -> Result<Option<Tree<Spanned>>, ParseError> {
+        let Some(part) = self.parse_part()? else {
+            return Ok(None);
+        };
+        let mut parts = vec![part];
+        loop {
+            if self.next_if(|t| matches!(t, Token::Join)).is_some() {
+                match self.parse_part()? {
+                    Some(part) => parts.push(part),
+                    None => return Err(unexpected(self.next())),
+                }
+            } else if matches!(parts.last(), Some(Tree::Literal(_)))
+                && self
+                    .next_if(|t| matches!(t, Token::Symbol(sym) if sym.as_str() == "="))
+                    .is_some()
+            {
+                parts.push(Tree::Literal("=".into()));
+                match self.parse_part()? {
+                    Some(part) => parts.push(part),
+                    None => return Err(unexpected(self.next())),
+                }
+            } else {
+                break;
             }
-            _ => todo!("Implement error handling for invalid substitution token"), // Invalid $ character!
         }
+
+        Ok(Some(join(parts)))
     }
 
-    fn parse_subshell(&mut self) -> Tree<String> {
-        Tree::Subshell(Box::new(self.parse_pipe()))
+    fn parse_part(&mut self) -> Result<Option<Tree<Spanned>>, ParseError> {
+        let Some(token) = self.next_if(|t| match t {
+            Token::Literal(_) | Token::Wildcard(_) => true,
+            Token::Symbol(sym) => matches!(sym.as_str(), "$" | "\"" | "\'" | "\\"),
+            _ => false,
+        }) else {
+            return Ok(None);
+        };
+        let part = match token {
+            Token::Literal(lit) => Tree::Literal(lit),
+            Token::Wildcard(pattern) => Tree::Glob(pattern),
+            Token::Symbol(sym) if sym.as_str() == "$" => self.parse_substitute(sym)?,
+            // An escaped character is quoted like a string of its own
+            Token::Symbol(sym) if sym.as_str() == "\\" => match self.next() {
+                Some(Token::Str(c)) => {
+                    let q = if c.as_str() == "'" { '"' } else { '\'' };
+                    Tree::String(Spanned {
+                        text: format!("{q}{c}{q}"),
+                        span: join_spans(sym.span, c.span),
+                    })
+                }
+                token => return Err(unexpected(token)),
+            },
+            Token::Symbol(sym) => self.parse_quote(sym)?,
+            _ => unreachable!("only the start of a part is taken"),
+        };
+        Ok(Some(part))
     }
 
-    fn parse_quote(&mut self, q: char) -> Tree<String> {
+    fn parse_redirect(&mut self) -> Result<Option<Redirection<Spanned>>, ParseError> {
+        let Some((fd, op)) = self.peek().and_then(|token| match token {
+            Token::Symbol(sym) => RedirectOp::parse(sym),
+            _ => None,
+        }) else {
+            return Ok(None);
+        };
+        self.next();
+
+        let Some(target) = self.parse_word()? else {
+            // Like bash, a redirection at the end of a line is missing its
+            // target rather than waiting for the next line
+            let token = self.next().unwrap_or(Token::Symbol("\n".into()));
+            return Err(ParseError::UnexpectedToken(token));
+        };
+        if op != RedirectOp::HereDoc {
+            return Ok(Some(Redirection { fd, op, target }));
+        }
+
+        // A document ended by the end of the input has an empty body
+        let mut body = match self.next_if(|t| matches!(t, Token::Str(_))) {
+            Some(Token::Str(body)) => body,
+            _ => Spanned::default(),
+        };
+        let target = match target {
+            // Only the body of a document with an unquoted delimiter is expanded
+            Tree::Literal(_) => parse_document(&body)?,
+            _ => {
+                body.insert(0, '\'');
+                body.push('\'');
+                Tree::String(body)
+            }
+        };
+        Ok(Some(Redirection { fd, op, target }))
+    }
+
+    fn parse_substitute(&mut self, dollar: Spanned) -> Result<Tree<Spanned>, ParseError> {
+        match self.next() {
+            Some(Token::Identifier(id)) if id.starts_with('{') => parse_parameter(&dollar, &id),
+            Some(Token::Identifier(mut id)) => Ok(Tree::Identifier(mem::take(&mut id))),
+            Some(Token::Symbol(open)) if open.as_str() == "(" => {
+                let subs = self.parse_subshell()?;
+                match self.next() {
+                    Some(Token::Symbol(sym)) if sym.as_str() == ")" => Ok(subs),
+                    None => Err(ParseError::UnbalancedParen(open)),
+                    token => Err(unexpected(token)),
+                }
+            }
+            Some(token) => {
+                let mut text = dollar;
+                text.push_str(token.inner());
+                text.span = join_spans(text.span, token.span());
+                Err(ParseError::InvalidSubstitution(text))
+            }
+            None => Err(ParseError::UnexpectedEof),
+        }
+    }
+
+    fn parse_subshell(&mut self) -> Result<Tree<Spanned>, ParseError> {
+        Ok(Tree::Subshell(Box::new(self.parse_list()?)))
+    }
+
+    fn parse_quote(&mut self, open: Spanned) -> Result<Tree<Spanned>, ParseError> {
+        let q = open.chars().next().unwrap_or('"');
         let mut quoted = vec![];
 
         loop {
-            let Some(Token::Str(mut string)) = self.next() else {
-                panic!("Unbalanced quotation mark!")
-            }; // mem::take(
-            let Some(Token::Symbol(sym)) = self.next() else {
-                panic!("Invalid string contents!")
+            let mut string = match self.next() {
+                Some(Token::Str(string)) => string,
+                None => return Err(ParseError::UnbalancedQuote(open)),
+                token => return Err(unexpected(token)),
+            };
+            let sym = match self.next() {
+                Some(Token::Symbol(sym)) => sym,
+                None => return Err(ParseError::UnbalancedQuote(open)),
+                token => return Err(unexpected(token)),
             };
 
             match sym.as_ref() {
                 "\"" | "\'" => {
-                    return if quoted.is_empty() {
+                    return Ok(if quoted.is_empty() {
                         string.insert(0, q);
                         string.push(q);
                         Tree::String(mem::take(&mut string))
                     } else {
                         quoted.push(Tree::String(mem::take(&mut string)));
                         Tree::Quote(q, mem::take(&mut quoted))
-                    }
+                    })
                 }
                 "$" => {
                     quoted.push(Tree::String(mem::take(&mut string)));
-                    quoted.push(self.parse_substitute());
+                    quoted.push(self.parse_substitute(sym)?);
                 }
-                _ => todo!("Implement error handling for invalid quote contents"),
+                _ => return Err(ParseError::UnexpectedToken(Token::Symbol(sym))),
             };
-            panic!("Parse error!")
+        }
+    }
+
+    /// Consumes the operator `sym` if it comes next.
+    fn next_symbol(&mut self, sym: &str) -> bool {
+        self.next_if(|t| matches!(t, Token::Symbol(s) if s.as_str() == sym))
+            .is_some()
+    }
+
+    /// Consumes the reserved word `word` if it comes next.
+    fn next_keyword(&mut self, word: &str) -> bool {
+        self.next_if(|t| matches!(t, Token::ControlOperator(w) if w.as_str() == word))
+            .is_some()
+    }
+
+    /// Consumes the literal word `word` if it comes next, such as the `in` of
+    /// a `for` loop.
+    fn next_literal(&mut self, word: &str) -> bool {
+        self.next_if(|t| matches!(t, Token::Literal(w) if w.as_str() == word))
+            .is_some()
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.next_symbol("\n") {}
+    }
+}
+
+/// Whether the reserved word `word` begins a compound command, rather than
+/// ending a list.
+fn starts_compound(word: &str) -> bool {
+    matches!(word, "if" | "while" | "until" | "for" | "case" | "function" | "{")
+}
+
+/// Parses text in which only `$` is special, such as the body of a
+/// here-document, as if it were double quoted.
+fn parse_document(text: &Spanned) -> Result<Tree<Spanned>, ParseError> {
+    let mut tokens = document_from(text, text.span.unwrap_or_default().start);
+    tokens.push(Token::Symbol("\"".into()));
+    tokens.into_iter().peekable().parse_quote("\"".into())
+}
+
+/// Makes a single word out of `parts`.
+///
+/// Adjacent literals become a single literal, so `a=b` stays `Literal("a=b")`.
+fn join(parts: Vec<Tree<Spanned>>) -> Tree<Spanned> {
+    let mut word: Vec<Tree<Spanned>> = vec![];
+    for part in parts {
+        match (word.last_mut(), part) {
+            (Some(Tree::Literal(prev)), Tree::Literal(lit)) => prev.append(&lit),
+            (_, part) => word.push(part),
+        }
+    }
+    match word.len() {
+        0 => Tree::Literal(Spanned::default()),
+        _ => Tree::Word(word).unwrap_word(),
+    }
+}
+
+/// Parses the contents of `${...}` into a parameter expansion, or a plain
+/// identifier if there is no operator.
+fn parse_parameter(dollar: &Spanned, id: &Spanned) -> Result<Tree<Spanned>, ParseError> {
+    let bad = || {
+        let mut text = dollar.clone();
+        text.append(id);
+        ParseError::InvalidSubstitution(text)
+    };
+    let Some(inner) = id.strip_prefix('{').and_then(|id| id.strip_suffix('}')) else {
+        return Err(bad());
+    };
+
+    if let Some(name) = inner.strip_prefix('#').filter(|name| is_parameter(name)) {
+        return Ok(Tree::Parameter {
+            name: id.slice(name),
+            op: ParamOp::Length,
+            args: vec![],
+        });
+    }
+
+    let end = match inner.chars().next() {
+        Some('?' | '$' | '!' | '#' | '@' | '*') => 1,
+        _ => inner
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(inner.len()),
+    };
+    let (name, rest) = inner.split_at(end);
+    if !is_parameter(name) {
+        return Err(bad());
+    }
+    if rest.is_empty() {
+        return Ok(Tree::Identifier(id.slice(name)));
+    }
+
+    let (null, unprefixed) = match rest.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let (op, args) = match unprefixed.chars().next() {
+        Some('-') => (ParamOp::Default { null }, &unprefixed[1..]),
+        Some('=') => (ParamOp::Assign { null }, &unprefixed[1..]),
+        Some('?') => (ParamOp::Error { null }, &unprefixed[1..]),
+        Some('+') => (ParamOp::Alternate { null }, &unprefixed[1..]),
+        _ if null => (ParamOp::Substring, unprefixed),
+        Some('#') => match rest.strip_prefix("##") {
+            Some(pattern) => (ParamOp::RemovePrefix { longest: true }, pattern),
+            None => (ParamOp::RemovePrefix { longest: false }, &rest[1..]),
+        },
+        Some('%') => match rest.strip_prefix("%%") {
+            Some(pattern) => (ParamOp::RemoveSuffix { longest: true }, pattern),
+            None => (ParamOp::RemoveSuffix { longest: false }, &rest[1..]),
+        },
+        Some('/') => match rest.strip_prefix("//") {
+            Some(args) => (ParamOp::Replace { all: true }, args),
+            None => (ParamOp::Replace { all: false }, &rest[1..]),
+        },
+        _ => return Err(bad()),
+    };
+
+    let args = match op {
+        ParamOp::Replace { .. } => {
+            let (pattern, replacement) = split_unescaped(args, '/').unwrap_or((args, ""));
+            vec![parse_text(&id.slice(pattern)), parse_text(&id.slice(replacement))]
+        }
+        // The offset and length are numbers, around which spaces don't count
+        ParamOp::Substring => match args.split_once(':') {
+            Some((offset, length)) => vec![
+                parse_text(&id.slice(offset.trim())),
+                parse_text(&id.slice(length.trim())),
+            ],
+            None => vec![parse_text(&id.slice(args.trim()))],
+        },
+        _ => vec![parse_text(&id.slice(args))],
+    };
+    Ok(Tree::Parameter {
+        name: id.slice(name),
+        op,
+        args: args.into_iter().collect::<Result<_, _>>().map_err(|_| bad())?,
+    })
+}
+
+/// Whether `name` can be expanded with `${name}`: a variable, a positional
+/// parameter such as `10`, or a special parameter such as `?`.
+fn is_parameter(name: &str) -> bool {
+    is_name(name)
+        || (!name.is_empty() && name.chars().all(|c| c.is_ascii_digit()))
+        || ["?", "$", "!", "#", "@", "*"].contains(&name)
+}
+
+/// Splits `text` at the first `sep` that isn't escaped with a backslash.
+fn split_unescaped(text: &str, sep: char) -> Option<(&str, &str)> {
+    let mut escaped = false;
+    let (i, _) = text.char_indices().find(|&(_, c)| {
+        let found = c == sep && !escaped;
+        escaped = c == '\\' && !escaped;
+        found
+    })?;
+    Some((&text[..i], &text[i + sep.len_utf8()..]))
+}
+
+/// Parses the argument of a parameter expansion as a single word, in which
+/// only quotes, `$` and `\` are special.
+fn parse_text(text: &Spanned) -> Result<Tree<Spanned>, ParseError> {
+    let mut tokens = operand_from(text, text.span.unwrap_or_default().start)
+        .into_iter()
+        .peekable();
+    let word = tokens.parse_word()?;
+    match tokens.next() {
+        Some(token) => Err(ParseError::UnexpectedToken(token)),
+        None => Ok(join(word.into_iter().collect())),
+    }
+}
+
+/// Splits a word written as `NAME=value` into the name and the value, or
+/// gives the word back if it isn't an assignment.
+fn split_assignment(word: Tree<Spanned>) -> Result<(Spanned, Tree<Spanned>), Tree<Spanned>> {
+    let mut parts = match word {
+        Tree::Word(parts) => parts,
+        word => vec![word],
+    };
+    let Some(Tree::Literal(first)) = parts.first() else {
+        return Err(Tree::Word(parts).unwrap_word());
+    };
+    let Some((name, value)) = first.split_once('=').filter(|(name, _)| is_name(name)) else {
+        return Err(Tree::Word(parts).unwrap_word());
+    };
+    let (name, value) = (first.slice(name), first.slice(value));
+
+    match value.is_empty() {
+        true => parts.remove(0),
+        false => mem::replace(&mut parts[0], Tree::Literal(value)),
+    };
+    let value = match parts.len() {
+        0 => Tree::Literal(Spanned::default()),
+        _ => Tree::Word(parts).unwrap_word(),
+    };
+    Ok((name, value))
+}
+
+impl<T> Tree<T>
+where
+    T: AsRef<str> + Clone,
+{
+    /// A word of a single part is just that part.
+    fn unwrap_word(self) -> Self {
+        match self {
+            Tree::Word(mut parts) if parts.len() == 1 => parts.remove(0),
+            tree => tree,
+        }
+    }
+
+    /// Shell text for the tree, rebuilt from its parts, to show in messages
+    /// such as `$x: ambiguous redirect`.
+    pub fn source(&self) -> String {
+        let join = |trees: &[Tree<T>], sep: &str| {
+            trees.iter().map(Tree::source).collect::<Vec<_>>().join(sep)
+        };
+        match self {
+            Tree::List(trees) => trees.iter().fold(String::new(), |mut list, tree| {
+                if !list.is_empty() {
+                    list.push_str(if list.ends_with('&') { " " } else { "; " });
+                }
+                list + &tree.source()
+            }),
+            Tree::And(left, right) => format!("{} && {}", left.source(), right.source()),
+            Tree::Or(left, right) => format!("{} || {}", left.source(), right.source()),
+            Tree::Pipe(left, right) => format!("{} | {}", left.source(), right.source()),
+            Tree::Background(tree) => format!("{} &", tree.source()),
+            Tree::If {
+                branches,
+                otherwise,
+            } => {
+                let mut text = String::new();
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    let keyword = if i == 0 { "if" } else { "elif" };
+                    text += &format!("{keyword} {}; then {}; ", condition.source(), body.source());
+                }
+                if let Some(otherwise) = otherwise {
+                    text += &format!("else {}; ", otherwise.source());
+                }
+                text + "fi"
+            }
+            Tree::While {
+                condition,
+                body,
+                until,
+            } => format!(
+                "{} {}; do {}; done",
+                if *until { "until" } else { "while" },
+                condition.source(),
+                body.source()
+            ),
+            Tree::For { name, words, body } => match words {
+                Some(words) => format!(
+                    "for {} in {}; do {}; done",
+                    name.as_ref(),
+                    join(words, " "),
+                    body.source()
+                ),
+                None => format!("for {}; do {}; done", name.as_ref(), body.source()),
+            },
+            Tree::Case { word, arms } => {
+                let mut text = format!("case {} in ", word.source());
+                for arm in arms {
+                    let end = match arm.end {
+                        CaseEnd::Break => ";;",
+                        CaseEnd::FallThrough => ";&",
+                        CaseEnd::Continue => ";;&",
+                    };
+                    text += &format!("{}) {}{end} ", join(&arm.patterns, "|"), arm.body.source());
+                }
+                text + "esac"
+            }
+            Tree::FunctionDef { name, body } => format!("{}() {}", name.as_ref(), body.source()),
+            Tree::Group {
+                body,
+                subshell: true,
+            } => format!("( {} )", body.source()),
+            Tree::Group { body, .. } => format!("{{ {}; }}", body.source()),
+            Tree::ArithFor {
+                init,
+                condition,
+                step,
+                body,
+            } => format!(
+                "for (({};{};{})); do {}; done",
+                init.unquoted(),
+                condition.unquoted(),
+                step.unquoted(),
+                body.source()
+            ),
+            Tree::Command { name, args } if args.is_empty() => name.source(),
+            Tree::Command { name, args } => format!("{} {}", name.source(), join(args, " ")),
+            Tree::Redirect { tree, redirects } => {
+                let mut text = tree.source();
+                for Redirection { fd, op, target } in redirects {
+                    let (default, op) = match op {
+                        RedirectOp::Read => (0, "<"),
+                        RedirectOp::Write => (1, ">"),
+                        RedirectOp::Append => (1, ">>"),
+                        RedirectOp::Duplicate if *fd == 0 => (0, "<&"),
+                        RedirectOp::Duplicate => (1, ">&"),
+                        RedirectOp::WriteAll => (1, "&>"),
+                        RedirectOp::AppendAll => (1, "&>>"),
+                        // The body of a document stands in for its delimiter
+                        RedirectOp::HereDoc => (0, "<<"),
+                        RedirectOp::HereString => (0, "<<<"),
+                    };
+                    let fd = if *fd == default { String::new() } else { fd.to_string() };
+                    let gap = if op.ends_with('&') { "" } else { " " };
+                    text += &format!(" {fd}{op}{gap}{}", target.source());
+                }
+                text
+            }
+            Tree::Assign { vars, command } => {
+                let vars = vars
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name.as_ref(), value.source()))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                match command {
+                    Some(command) => format!("{vars} {}", command.source()),
+                    None => vars,
+                }
+            }
+            Tree::Quote(q, _) => format!("{q}{}{q}", self.unquoted()),
+            Tree::Subshell(tree) => format!("$({})", tree.source()),
+            Tree::Parameter { name, op, args } => {
+                let name = name.as_ref();
+                let colon = |null: bool| if null { ":" } else { "" };
+                let arg = |i: usize| args.get(i).map(Tree::unquoted).unwrap_or_default();
+                match op {
+                    ParamOp::Length => format!("${{#{name}}}"),
+                    ParamOp::Default { null } => format!("${{{name}{}-{}}}", colon(*null), arg(0)),
+                    ParamOp::Assign { null } => format!("${{{name}{}={}}}", colon(*null), arg(0)),
+                    ParamOp::Error { null } => format!("${{{name}{}?{}}}", colon(*null), arg(0)),
+                    ParamOp::Alternate { null } => {
+                        format!("${{{name}{}+{}}}", colon(*null), arg(0))
+                    }
+                    ParamOp::RemovePrefix { longest } => {
+                        format!("${{{name}{}{}}}", if *longest { "##" } else { "#" }, arg(0))
+                    }
+                    ParamOp::RemoveSuffix { longest } => {
+                        format!("${{{name}{}{}}}", if *longest { "%%" } else { "%" }, arg(0))
+                    }
+                    ParamOp::Replace { all } => format!(
+                        "${{{name}{}{}/{}}}",
+                        if *all { "//" } else { "/" },
+                        arg(0),
+                        arg(1)
+                    ),
+                    ParamOp::Substring => format!("${{{name}:{}}}", join(args, ":")),
+                }
+            }
+            Tree::Word(parts) => {
+                let mut text = String::new();
+                for (i, part) in parts.iter().enumerate() {
+                    // A name needs braces if the next part would go on with it
+                    let braced = match (part, parts.get(i + 1)) {
+                        (Tree::Identifier(id), Some(next)) => {
+                            is_name(id.as_ref())
+                                && next
+                                    .source()
+                                    .starts_with(|c: char| c.is_alphanumeric() || c == '_')
+                        }
+                        _ => false,
+                    };
+                    match part {
+                        Tree::Identifier(id) if braced => text += &format!("${{{}}}", id.as_ref()),
+                        part => text += &part.source(),
+                    }
+                }
+                text
+            }
+            Tree::Literal(text) | Tree::Glob(text) | Tree::String(text) => {
+                text.as_ref().to_string()
+            }
+            Tree::Identifier(id) => format!("${}", id.as_ref()),
+        }
+    }
+
+    /// The text inside a quote, or the expression of an arithmetic `for`,
+    /// which is parsed like the inside of one.
+    fn unquoted(&self) -> String {
+        match self {
+            Tree::Quote(_, parts) => parts
+                .iter()
+                .map(|part| match part {
+                    Tree::String(s) => s.as_ref().to_string(),
+                    part => part.source(),
+                })
+                .collect(),
+            Tree::String(s) if s.as_ref().len() >= 2 => {
+                let s = s.as_ref();
+                s[1..s.len() - 1].to_string()
+            }
+            tree => tree.source(),
+        }
+    }
+}
+
+impl Tree<Spanned> {
+    /// The stretch of the source this tree was parsed from, as far as the
+    /// text it kept can tell.
+    pub fn span(&self) -> Option<Span> {
+        let all = |trees: &[Tree<Spanned>]| {
+            trees.iter().map(Tree::span).fold(None, join_spans)
+        };
+        match self {
+            Tree::List(trees) | Tree::Quote(_, trees) | Tree::Word(trees) => all(trees),
+            Tree::And(left, right) | Tree::Or(left, right) | Tree::Pipe(left, right) => {
+                join_spans(left.span(), right.span())
+            }
+            Tree::Background(tree) | Tree::Subshell(tree) => tree.span(),
+            Tree::Group { body, .. } => body.span(),
+            Tree::If {
+                branches,
+                otherwise,
+            } => branches
+                .iter()
+                .map(|(condition, body)| join_spans(condition.span(), body.span()))
+                .chain(otherwise.iter().map(|tree| tree.span()))
+                .fold(None, join_spans),
+            Tree::While {
+                condition, body, ..
+            } => join_spans(condition.span(), body.span()),
+            Tree::For { name, words, body } => join_spans(
+                join_spans(name.span, words.as_deref().and_then(all)),
+                body.span(),
+            ),
+            Tree::Case { word, arms } => arms
+                .iter()
+                .map(|arm| join_spans(all(&arm.patterns), arm.body.span()))
+                .fold(word.span(), join_spans),
+            Tree::FunctionDef { name, body } => join_spans(name.span, body.span()),
+            Tree::ArithFor {
+                init,
+                condition,
+                step,
+                body,
+            } => [init, condition, step, body]
+                .iter()
+                .map(|tree| tree.span())
+                .fold(None, join_spans),
+            Tree::Command { name, args } => join_spans(name.span(), all(args)),
+            Tree::Redirect { tree, redirects } => redirects
+                .iter()
+                .map(|redirect| redirect.target.span())
+                .fold(tree.span(), join_spans),
+            Tree::Assign { vars, command } => vars
+                .iter()
+                .map(|(name, value)| join_spans(name.span, value.span()))
+                .chain(command.iter().map(|tree| tree.span()))
+                .fold(None, join_spans),
+            Tree::Parameter { name, args, .. } => join_spans(name.span, all(args)),
+            Tree::Literal(text)
+            | Tree::Glob(text)
+            | Tree::Identifier(text)
+            | Tree::String(text) => text.span,
         }
     }
 }
 
 pub trait Parse {
-    fn parse(self) -> Tree<String>;
+    fn parse(self) -> Result<Tree<Spanned>, ParseError>;
 }
 
 impl<T> Parse for Vec<Token<T>>
 where
-    T: AsRef<str> + Clone + Default + ToString,
+    T: AsRef<str> + Clone + Into<Spanned>,
 {
-    fn parse(mut self) -> Tree<String> {
-        self.iter_mut()
-            .map(|t| t.inner_to_string())
-            .collect()
+    fn parse(self) -> Result<Tree<Spanned>, ParseError> {
+        self.into_iter().map(Token::into_spanned).collect()
     }
 }
 
@@ -192,14 +1363,14 @@ where
     T: AsRef<str> + Clone + Default + ToString,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        fn vec_to_string<T>(v: &Vec<Tree<T>>, l_pad: String) -> String 
+        fn vec_to_string<T>(v: &[Tree<T>], l_pad: String) -> String 
         where 
             T: AsRef<str> + Clone + Default + ToString
         {
             let Some((last, rest)) = v.split_last() else {
                 return "".into();
             };
-            rest.into_iter().fold("".into(), |s, arg| {
+            rest.iter().fold("".into(), |s, arg| {
                 format!("{s}\n{l_pad}├──{}", helper(arg, format!("{l_pad}│   ")))
             }) + format!("\n{l_pad}└──{}", helper(last, format!("{l_pad}    "))).as_str()
         }
@@ -209,6 +1380,84 @@ where
             T: AsRef<str> + Clone + Default + ToString
         {
             match tree {
+                Tree::List(trees) => format!(
+                    "LIST{}",
+                    vec_to_string(trees, l_pad)
+                ),
+                Tree::And(l, r) => format!(
+                    "AND\n{l_pad}├──{}\n{l_pad}└──{}",
+                    helper(l, format!("{l_pad}│   ")),
+                    helper(r, format!("{l_pad}    "))
+                ),
+                Tree::Or(l, r) => format!(
+                    "OR\n{l_pad}├──{}\n{l_pad}└──{}",
+                    helper(l, format!("{l_pad}│   ")),
+                    helper(r, format!("{l_pad}    "))
+                ),
+                Tree::Background(tree) => format!(
+                    "BACKGROUND\n{l_pad}└──{}",
+                    helper(tree, format!("{l_pad}    "))
+                ),
+                Tree::If { branches, otherwise } => format!(
+                    "IF{}{}",
+                    branches.iter().enumerate().fold("".into(), |s, (i, (condition, body))| {
+                        let last = i + 1 == branches.len() && otherwise.is_none();
+                        let (branch, pad) = if last { ("└──", "    ") } else { ("├──", "│   ") };
+                        format!(
+                            "{s}\n{l_pad}{branch}CONDITION\n{l_pad}{pad}├──{}\n{l_pad}{pad}└──{}",
+                            helper(condition, format!("{l_pad}{pad}│   ")),
+                            helper(body, format!("{l_pad}{pad}    "))
+                        )
+                    }),
+                    otherwise.as_ref().map_or("".into(), |otherwise| format!(
+                        "\n{l_pad}└──ELSE\n{l_pad}    └──{}",
+                        helper(otherwise, format!("{l_pad}        "))
+                    ))
+                ),
+                Tree::While { condition, body, until } => format!(
+                    "{}\n{l_pad}├──{}\n{l_pad}└──{}",
+                    if *until { "UNTIL" } else { "WHILE" },
+                    helper(condition, format!("{l_pad}│   ")),
+                    helper(body, format!("{l_pad}    "))
+                ),
+                Tree::For { name, words, body } => format!(
+                    "FOR: {}\n{l_pad}├──IN{}\n{l_pad}└──{}",
+                    name.to_string(),
+                    words.as_ref().map_or(" \"$@\"".into(), |words| {
+                        vec_to_string(words, format!("{l_pad}│   "))
+                    }),
+                    helper(body, format!("{l_pad}    "))
+                ),
+                Tree::Case { word, arms } => format!(
+                    "CASE\n{l_pad}├──{}{}",
+                    helper(word, format!("{l_pad}│   ")),
+                    arms.iter().enumerate().fold("".into(), |s, (i, arm)| {
+                        let (branch, pad) = if i + 1 == arms.len() { ("└──", "    ") } else { ("├──", "│   ") };
+                        format!(
+                            "{s}\n{l_pad}{branch}ARM {:?}{}\n{l_pad}{pad}└──{}",
+                            arm.end,
+                            vec_to_string(&arm.patterns, format!("{l_pad}{pad}│   ")),
+                            helper(&arm.body, format!("{l_pad}{pad}    "))
+                        )
+                    })
+                ),
+                Tree::FunctionDef { name, body } => format!(
+                    "FUNCTION: {}\n{l_pad}└──{}",
+                    name.to_string(),
+                    helper(body, format!("{l_pad}    "))
+                ),
+                Tree::Group { body, subshell } => format!(
+                    "{}\n{l_pad}└──{}",
+                    if *subshell { "GROUP ( )" } else { "GROUP { }" },
+                    helper(body, format!("{l_pad}    "))
+                ),
+                Tree::ArithFor { init, condition, step, body } => format!(
+                    "FOR\n{l_pad}├──{}\n{l_pad}├──{}\n{l_pad}├──{}\n{l_pad}└──{}",
+                    helper(init, format!("{l_pad}│   ")),
+                    helper(condition, format!("{l_pad}│   ")),
+                    helper(step, format!("{l_pad}│   ")),
+                    helper(body, format!("{l_pad}    "))
+                ),
                 Tree::Pipe(l, r) => format!(
                     "PIPE\n{l_pad}├──{}\n{l_pad}└──{}",
                     helper(l, format!("{l_pad}│   ")),
@@ -219,6 +1468,41 @@ where
                     helper(name, format!("{l_pad}│   ")),
                     vec_to_string(args, format!("{l_pad}    "))
                 ),
+                Tree::Redirect { tree, redirects } => format!(
+                    "REDIRECT\n{l_pad}├──{}\n{l_pad}└──IO{}",
+                    helper(tree, format!("{l_pad}│   ")),
+                    redirects.iter().enumerate().fold("".into(), |s, (i, r)| {
+                        let branch = if i + 1 == redirects.len() { "└──" } else { "├──" };
+                        format!(
+                            "{s}\n{l_pad}    {branch}{:?} {}: {}",
+                            r.op,
+                            r.fd,
+                            helper(&r.target, format!("{l_pad}        "))
+                        )
+                    })
+                ),
+                Tree::Assign { vars, command } => format!(
+                    "ASSIGN{}{}",
+                    vars.iter().enumerate().fold("".into(), |s, (i, (name, value))| {
+                        let last = i + 1 == vars.len() && command.is_none();
+                        let (branch, pad) = if last { ("└──", "    ") } else { ("├──", "│   ") };
+                        format!(
+                            "{s}\n{l_pad}{branch}{} = {}",
+                            name.to_string(),
+                            helper(value, format!("{l_pad}{pad}"))
+                        )
+                    }),
+                    command.as_ref().map_or("".into(), |command| format!(
+                        "\n{l_pad}└──{}",
+                        helper(command, format!("{l_pad}    "))
+                    ))
+                ),
+                Tree::Parameter { name, op, args } => format!(
+                    "PARAMETER: {} {:?}{}",
+                    name.to_string(),
+                    op,
+                    vec_to_string(args, l_pad)
+                ),
                 Tree::Subshell(line) => format!(
                     "SUBSHELL\n{l_pad}└──{}",
                     helper(line, format!("{l_pad}    "))
@@ -227,7 +1511,12 @@ where
                     "QUOTE\n{l_pad}└──{}",
                     vec_to_string(v, format!("{l_pad}    "))
                 ),
+                Tree::Word(parts) => format!(
+                    "WORD{}",
+                    vec_to_string(parts, l_pad)
+                ),
                 Tree::Literal(lit) => format!("LITERAL: {}", lit.to_string()),
+                Tree::Glob(pattern) => format!("GLOB: {}", pattern.to_string()),
                 Tree::Identifier(id) => format!("IDENT: {}", id.to_string()),
                 Tree::String(s) => format!("STRING: {}", s.to_string()),
             }
@@ -243,7 +1532,69 @@ where
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::List(trees) => write!(
+                f,
+                "List(vec![{}])",
+                trees
+                    .iter()
+                    .map(|tree| tree.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Self::And(l, r) => write!(f, "And(Box::new({l}), Box::new({r}))"),
+            Self::Or(l, r) => write!(f, "Or(Box::new({l}), Box::new({r}))"),
+            Self::Background(tree) => write!(f, "Background(Box::new({tree}))"),
             Self::Pipe(l, r) => write!(f, "Pipe(Box::new({l}), Box::new({r}))"),
+            Self::While { condition, body, until } => write!(
+                f,
+                "While {{ condition: Box::new({condition}), body: Box::new({body}), until: {until} }}"
+            ),
+            Self::For { name, words, body } => write!(
+                f,
+                "For {{ name: \"{}\", words: {}, body: Box::new({body}) }}",
+                name.to_string(),
+                words.as_ref().map_or("None".into(), |words| format!(
+                    "Some(vec![{}])",
+                    words
+                        .iter()
+                        .map(|word| word.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ))
+            ),
+            Self::Case { word, arms } => write!(
+                f,
+                "Case {{ word: Box::new({word}), arms: vec![{}] }}",
+                arms.iter()
+                    .map(|arm| arm.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Self::FunctionDef { name, body } => write!(
+                f,
+                "FunctionDef {{ name: \"{}\", body: Box::new({body}) }}",
+                name.to_string()
+            ),
+            Self::Group { body, subshell } => write!(
+                f,
+                "Group {{ body: Box::new({body}), subshell: {subshell} }}"
+            ),
+            Self::ArithFor { init, condition, step, body } => write!(
+                f,
+                "ArithFor {{ init: Box::new({init}), condition: Box::new({condition}), step: Box::new({step}), body: Box::new({body}) }}"
+            ),
+            Self::If { branches, otherwise } => write!(
+                f,
+                "If {{ branches: vec![{}], otherwise: {} }}",
+                branches
+                    .iter()
+                    .map(|(condition, body)| format!("({condition}, {body})"))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                otherwise
+                    .as_ref()
+                    .map_or("None".into(), |otherwise| format!("Some(Box::new({otherwise}))"))
+            ),
             Self::Command { name, args } => write!(
                 f,
                 "Command {{ name: Box::new({}), args: vec![{}] }}",
@@ -253,6 +1604,36 @@ where
                     .collect::<Vec<String>>()
                     .join(", ")
             ),
+            Self::Redirect { tree, redirects } => write!(
+                f,
+                "Redirect {{ tree: Box::new({tree}), redirects: vec![{}] }}",
+                redirects
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Self::Assign { vars, command } => write!(
+                f,
+                "Assign {{ vars: vec![{}], command: {} }}",
+                vars.iter()
+                    .map(|(name, value)| format!("(\"{}\", {value})", name.to_string()))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                command
+                    .as_ref()
+                    .map_or("None".into(), |command| format!("Some(Box::new({command}))"))
+            ),
+            Self::Parameter { name, op, args } => write!(
+                f,
+                "Parameter {{ name: \"{}\", op: ParamOp::{:?}, args: vec![{}] }}",
+                name.to_string(),
+                op,
+                args.iter()
+                    .map(|arg| arg.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
             Self::Quote(ch, v) => write!(
                 f,
                 "Quote({ch}, vec![{}])",
@@ -262,37 +1643,21 @@ where
                     .join("")
             ),
             Self::Subshell(s) => write!(f, "Subshell(Box::new({s}))"),
+            Self::Word(parts) => write!(
+                f,
+                "Word(vec![{}])",
+                parts
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
             Self::Literal(lit) => write!(f, "Literal(\"{}\")", lit.to_string()),
+            Self::Glob(pattern) => write!(f, "Glob(\"{}\")", pattern.to_string()),
             Self::Identifier(id) => write!(f, "Identifier(\"{}\")", id.to_string()),
             Self::String(s) => write!(f, "String(r#\"{}\"#)", s.to_string()),
         }
     }
-    // fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    //     match self {
-    //         Self::Pipe(l, r) => write!(f, "{l} | {r}"),
-    //         Self::Command { name, args } => write!(
-    //             f,
-    //             "{} {}",
-    //             name.as_ref(),
-    //             args.iter()
-    //                 .map(|arg| arg.to_string())
-    //                 .collect::<Vec<String>>()
-    //                 .join(" ")
-    //         ),
-    //         Self::Subshell(_) => todo!(),
-    //         Self::Literal(lit) => write!(f, "{lit}"),
-    //         Self::Identifier(id) => write!(f, "${id}"),
-    //         Self::String(s) => write!(f, "{s}"),
-    //         Self::Quote(ch, v) => write!(
-    //             f,
-    //             "{ch}{}{ch}",
-    //             v.iter()
-    //                 .map(|s| s.to_string())
-    //                 .collect::<Vec<String>>()
-    //                 .join("")
-    //         ),
-    //     }
-    // }
 }
 
 
@@ -304,14 +1669,114 @@ where
     fn eq(&self, other: &Tree<U>) -> bool {
         use Tree::*;
         match (self, other) {
+            (List(a), List(b)) => a == b,
+            (And(a1, a2), And(b1, b2)) | (Or(a1, a2), Or(b1, b2)) => (a1.as_ref() == b1.as_ref()) && (a2.as_ref() == b2.as_ref()),
+            (Background(a), Background(b)) => a.as_ref() == b.as_ref(),
+            (If { branches: a1, otherwise: a2 }, If { branches: b1, otherwise: b2 }) => {
+                a1.len() == b1.len()
+                    && a1.iter().zip(b1).all(|((c1, t1), (c2, t2))| c1 == c2 && t1 == t2)
+                    && match (a2, b2) {
+                        (Some(a), Some(b)) => a.as_ref() == b.as_ref(),
+                        (None, None) => true,
+                        _ => false,
+                    }
+            }
+            (While { condition: a1, body: a2, until: a3 }, While { condition: b1, body: b2, until: b3 }) => {
+                a1.as_ref() == b1.as_ref() && a2.as_ref() == b2.as_ref() && a3 == b3
+            }
+            (For { name: a1, words: a2, body: a3 }, For { name: b1, words: b2, body: b3 }) => {
+                a1.as_ref() == b1.as_ref()
+                    && match (a2, b2) {
+                        (Some(a), Some(b)) => a == b,
+                        (None, None) => true,
+                        _ => false,
+                    }
+                    && a3.as_ref() == b3.as_ref()
+            }
+            (Case { word: a1, arms: a2 }, Case { word: b1, arms: b2 }) => (a1.as_ref() == b1.as_ref()) && (a2 == b2),
+            (FunctionDef { name: a1, body: a2 }, FunctionDef { name: b1, body: b2 }) => (a1.as_ref() == b1.as_ref()) && (a2.as_ref() == b2.as_ref()),
+            (Group { body: a1, subshell: a2 }, Group { body: b1, subshell: b2 }) => (a1.as_ref() == b1.as_ref()) && (a2 == b2),
+            (ArithFor { init: a1, condition: a2, step: a3, body: a4 }, ArithFor { init: b1, condition: b2, step: b3, body: b4 }) => {
+                a1.as_ref() == b1.as_ref()
+                    && a2.as_ref() == b2.as_ref()
+                    && a3.as_ref() == b3.as_ref()
+                    && a4.as_ref() == b4.as_ref()
+            }
             (Pipe(a1, a2), Pipe(b1, b2)) => (a1.as_ref() == b1.as_ref()) && (a2.as_ref() == b2.as_ref()),
             (Command {name: a1, args: a2}, Command { name: b1, args: b2 }) => (a1.as_ref() == b1.as_ref()) && (a2 == b2),
+            (Redirect { tree: a1, redirects: a2 }, Redirect { tree: b1, redirects: b2 }) => (a1.as_ref() == b1.as_ref()) && (a2 == b2),
+            (Assign { vars: a1, command: a2 }, Assign { vars: b1, command: b2 }) => {
+                a1.len() == b1.len()
+                    && a1.iter().zip(b1).all(|((n1, v1), (n2, v2))| n1.as_ref() == n2.as_ref() && v1 == v2)
+                    && match (a2, b2) {
+                        (Some(a), Some(b)) => a.as_ref() == b.as_ref(),
+                        (None, None) => true,
+                        _ => false,
+                    }
+            }
+            (Parameter { name: a1, op: a2, args: a3 }, Parameter { name: b1, op: b2, args: b3 }) => {
+                (a1.as_ref() == b1.as_ref()) && (a2 == b2) && (a3 == b3)
+            }
             (Quote(a1, a2), Quote(b1, b2)) => (a1 == b1) && (a2 == b2),
             (Subshell(a), Subshell(b)) => a.as_ref() == b.as_ref(),
+            (Word(a), Word(b)) => a == b,
             (Literal(a), Literal(b))
+            | (Glob(a), Glob(b))
             | (Identifier(a), Identifier(b))
             | (String(a), String(b)) => a.as_ref() == b.as_ref(),
             _ => false,
         }
     }
-}
\ No newline at end of file
+}
+
+impl<T> std::fmt::Display for Redirection<T>
+where
+    T: AsRef<str> + Clone + Default + ToString,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Redirection {{ fd: {}, op: RedirectOp::{:?}, target: {} }}",
+            self.fd, self.op, self.target
+        )
+    }
+}
+
+impl<T, U> PartialEq<Redirection<U>> for Redirection<T>
+where
+    T: AsRef<str> + Clone,
+    U: AsRef<str> + Clone,
+{
+    fn eq(&self, other: &Redirection<U>) -> bool {
+        (self.fd == other.fd) && (self.op == other.op) && (self.target == other.target)
+    }
+}
+
+impl<T> std::fmt::Display for CaseArm<T>
+where
+    T: AsRef<str> + Clone + Default + ToString,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "CaseArm {{ patterns: vec![{}], body: {}, end: CaseEnd::{:?} }}",
+            self.patterns
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect::<Vec<String>>()
+                .join(", "),
+            self.body,
+            self.end
+        )
+    }
+}
+
+impl<T, U> PartialEq<CaseArm<U>> for CaseArm<T>
+where
+    T: AsRef<str> + Clone,
+    U: AsRef<str> + Clone,
+{
+    fn eq(&self, other: &CaseArm<U>) -> bool {
+        (self.patterns == other.patterns) && (self.body == other.body) && (self.end == other.end)
+    }
+}
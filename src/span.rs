@@ -0,0 +1,213 @@
+//! Where tokens and trees came from in the source, so that errors can point
+//! at the text they are about.
+use std::cmp::{max, min};
+use std::fmt::{self, Display};
+use std::ops::{Deref, DerefMut};
+
+/// A position in the source. Lines and columns count from 1, and columns
+/// count characters rather than bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    /// Offset from the start of the source, in bytes
+    pub byte: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self {
+            byte: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Position {
+    /// The position right after `c`, when `c` is found at this one.
+    pub fn after(self, c: char) -> Self {
+        match c {
+            '\n' => Self {
+                byte: self.byte + 1,
+                line: self.line + 1,
+                column: 1,
+            },
+            c => Self {
+                byte: self.byte + c.len_utf8(),
+                column: self.column + 1,
+                ..self
+            },
+        }
+    }
+
+    /// The position right after `text`, when `text` starts at this one.
+    pub fn after_str(self, text: &str) -> Self {
+        text.chars().fold(self, Self::after)
+    }
+}
+
+/// A stretch of the source, from `start` up to but not including `end`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Self { start, end }
+    }
+
+    /// An empty span at `pos`, such as the end of the input.
+    pub fn at(pos: Position) -> Self {
+        Self::new(pos, pos)
+    }
+
+    /// The smallest span covering both spans.
+    pub fn to(self, other: Span) -> Self {
+        Self::new(min(self.start, other.start), max(self.end, other.end))
+    }
+}
+
+/// The text of a token or a tree, along with where it was found.
+///
+/// Only the text is compared, so that tokens and trees are equal wherever
+/// they came from. Text made up by the parser rather than read from the
+/// source has no span.
+#[derive(Clone, Debug, Default)]
+pub struct Spanned {
+    pub text: String,
+    pub span: Option<Span>,
+}
+
+impl Spanned {
+    pub fn new(text: impl Into<String>, span: Span) -> Self {
+        Self {
+            text: text.into(),
+            span: Some(span),
+        }
+    }
+
+    /// The part of this text that `part`, a slice of it, covers.
+    pub fn slice(&self, part: &str) -> Self {
+        let offset = (part.as_ptr() as usize)
+            .checked_sub(self.text.as_ptr() as usize)
+            .filter(|offset| offset + part.len() <= self.text.len());
+        let span = match (self.span, offset) {
+            (Some(span), Some(offset)) => {
+                let start = span.start.after_str(&self.text[..offset]);
+                Some(Span::new(start, start.after_str(part)))
+            }
+            _ => None,
+        };
+        Self {
+            text: part.into(),
+            span,
+        }
+    }
+
+    /// Adds `other` to the end of this text, covering both spans.
+    pub fn append(&mut self, other: &Spanned) {
+        self.text.push_str(&other.text);
+        self.span = join_spans(self.span, other.span);
+    }
+}
+
+/// The span covering both spans, if either is known.
+pub fn join_spans(a: Option<Span>, b: Option<Span>) -> Option<Span> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.to(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+impl Deref for Spanned {
+    type Target = String;
+
+    fn deref(&self) -> &String {
+        &self.text
+    }
+}
+
+impl DerefMut for Spanned {
+    fn deref_mut(&mut self) -> &mut String {
+        &mut self.text
+    }
+}
+
+impl AsRef<str> for Spanned {
+    fn as_ref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl Display for Spanned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl PartialEq for Spanned {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl PartialEq<str> for Spanned {
+    fn eq(&self, other: &str) -> bool {
+        self.text == other
+    }
+}
+
+impl From<String> for Spanned {
+    fn from(text: String) -> Self {
+        Self { text, span: None }
+    }
+}
+
+impl From<&str> for Spanned {
+    fn from(text: &str) -> Self {
+        text.to_string().into()
+    }
+}
+
+impl From<char> for Spanned {
+    fn from(c: char) -> Self {
+        c.to_string().into()
+    }
+}
+
+/// Renders `message` followed by the line of `source` that `span` starts on,
+/// with the span underlined:
+///
+/// ```text
+/// syntax error near unexpected token `)'
+///  --> 1:8
+///   |
+/// 1 | echo a )
+///   |        ^
+/// ```
+pub fn render(source: &str, span: Span, message: impl Display) -> String {
+    let line = source.split('\n').nth(span.start.line - 1).unwrap_or("");
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    let number = span.start.line.to_string();
+    let gutter = " ".repeat(number.len());
+
+    // Tabs are kept so that the underline lines up with the text above it
+    let indent: String = line
+        .chars()
+        .take(span.start.column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let width = match span.end.line == span.start.line {
+        true => span.end.column.saturating_sub(span.start.column),
+        false => line.chars().count() + 1 - span.start.column,
+    };
+    let underline = format!("^{}", "~".repeat(width.saturating_sub(1)));
+
+    format!(
+        "{message}\n{gutter}--> {}:{}\n{gutter} |\n{number} | {line}\n{gutter} | {indent}{underline}",
+        span.start.line, span.start.column
+    )
+}
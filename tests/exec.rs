@@ -0,0 +1,810 @@
+use std::fs::{self, File};
+use std::sync::Arc;
+
+use lang::{
+    exec::{Flow, Shell, NOT_FOUND},
+    lexer::Tokenize,
+    parser::Parse,
+};
+
+fn run(line: &str) -> i32 {
+    Shell::new().execute(&line.tokenize().parse().unwrap())
+}
+
+#[test]
+fn exit_status() {
+    assert_eq!(run("true"), 0);
+    assert_eq!(run("false"), 1);
+    assert_eq!(run(r#"sh -c "exit 3""#), 3);
+}
+
+#[test]
+fn path_lookup() {
+    assert_eq!(run("/bin/sh -c 'exit 0'"), 0);
+    assert_eq!(run("this-command-does-not-exist"), NOT_FOUND);
+    assert_eq!(run("./no/such/program"), NOT_FOUND);
+}
+
+#[test]
+fn status_is_recorded() {
+    let mut shell = Shell::new();
+    shell.execute(&"false".tokenize().parse().unwrap());
+    assert_eq!(shell.status, 1);
+    shell.execute(&"true".tokenize().parse().unwrap());
+    assert_eq!(shell.status, 0);
+}
+
+/// Runs `line` with its stdout redirected to a temporary file and returns
+/// what it wrote.
+fn output(shell: &mut Shell, line: &str) -> String {
+    let path = std::env::temp_dir().join(format!(
+        "rush-test-{}-{:?}",
+        std::process::id(),
+        std::thread::current().id()
+    ));
+    shell.io.stdout = Arc::new(File::create(&path).unwrap());
+    shell.execute(&line.tokenize().parse().unwrap());
+    shell.io.stdout = Arc::new(File::create("/dev/null").unwrap());
+    let out = fs::read_to_string(&path).unwrap();
+    fs::remove_file(path).unwrap();
+    out
+}
+
+#[test]
+fn pipelines() {
+    let mut shell = Shell::new();
+    assert_eq!(output(&mut shell, "echo hello | tr a-z A-Z"), "HELLO\n");
+    assert_eq!(
+        output(&mut shell, "printf 'b\\na\\nb\\n' | sort | uniq -c | wc -l"),
+        "2\n"
+    );
+    // `yes` never finishes on its own, so this only returns if every stage
+    // runs at the same time
+    assert_eq!(output(&mut shell, "yes | head -n 2"), "y\ny\n");
+    // and only if a stage the shell runs itself stops once nothing reads it
+    assert_eq!(
+        output(&mut shell, "while true; do echo y; done | head -n 1"),
+        "y\n"
+    );
+}
+
+#[test]
+fn pipeline_status() {
+    assert_eq!(run("false | true"), 0);
+    assert_eq!(run("true | false"), 1);
+    assert_eq!(run("sh -c 'exit 3' | sh -c 'exit 4' | true"), 0);
+
+    let mut shell = Shell::new();
+    shell.execute(&"set -o pipefail".tokenize().parse().unwrap());
+    assert!(shell.options.pipefail);
+    assert_eq!(
+        shell.execute(&"sh -c 'exit 3' | sh -c 'exit 4' | true".tokenize().parse().unwrap()),
+        4
+    );
+    assert_eq!(shell.execute(&"false | true | true".tokenize().parse().unwrap()), 1);
+    assert_eq!(shell.execute(&"true | true".tokenize().parse().unwrap()), 0);
+
+    shell.execute(&"set +o pipefail".tokenize().parse().unwrap());
+    assert_eq!(shell.execute(&"false | true".tokenize().parse().unwrap()), 0);
+}
+
+#[test]
+fn redirections() {
+    let dir = std::env::temp_dir().join(format!("rush-redirections-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("out").display().to_string();
+
+    let mut shell = Shell::new();
+    assert_eq!(run(&format!("echo hello > {file}")), 0);
+    assert_eq!(fs::read_to_string(&file).unwrap(), "hello\n");
+    assert_eq!(run(&format!("echo world >> {file}")), 0);
+    assert_eq!(
+        output(&mut shell, &format!("tr a-z A-Z < {file}")),
+        "HELLO\nWORLD\n"
+    );
+
+    // `2>&1` duplicates whatever stdout is at that point
+    assert_eq!(
+        run(&format!("sh -c 'echo out; echo err >&2' > {file} 2>&1")),
+        0
+    );
+    assert_eq!(fs::read_to_string(&file).unwrap(), "out\nerr\n");
+    assert_eq!(
+        output(&mut shell, "sh -c 'echo err >&2' 2>&1 | wc -l"),
+        "1\n"
+    );
+    assert_eq!(run(&format!("sh -c 'echo out; echo err >&2' &> {file}")), 0);
+    assert_eq!(fs::read_to_string(&file).unwrap(), "out\nerr\n");
+
+    assert_eq!(run(&format!("cat < {}", dir.join("missing").display())), 1);
+    assert_eq!(run("echo hi 3> /dev/null"), 1);
+
+    // A target that is not one word is reported as it was written
+    assert_eq!(
+        output(&mut shell, "{ echo hi > $x; } 2>&1"),
+        "rush: $x: ambiguous redirect\n"
+    );
+    File::create(dir.join("other")).unwrap();
+    assert_eq!(
+        output(&mut shell, &format!("{{ echo hi > {}/*; }} 2>&1", dir.display())),
+        format!("rush: {}/*: ambiguous redirect\n", dir.display())
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn here_documents() {
+    let mut shell = Shell::new();
+    assert_eq!(
+        output(&mut shell, "cat <<EOF\nfirst line\n  second 'line'\nEOF"),
+        "first line\n  second 'line'\n"
+    );
+    assert_eq!(
+        output(&mut shell, "cat <<-'EOF' | wc -l\n\tone\n\ttwo\n\tEOF"),
+        "2\n"
+    );
+    assert_eq!(output(&mut shell, "tr a-z A-Z <<< 'here string'"), "HERE STRING\n");
+
+    // Larger than a pipe's buffer
+    let big = "x".repeat(1 << 17);
+    assert_eq!(
+        output(&mut shell, &format!("wc -c <<EOF\n{big}\nEOF")).trim(),
+        ((1 << 17) + 1).to_string()
+    );
+}
+
+#[test]
+fn command_substitution() {
+    let mut shell = Shell::new();
+    assert_eq!(output(&mut shell, "echo $(echo a   b) c"), "a b c\n");
+    assert_eq!(output(&mut shell, "echo $(echo $(echo nested))"), "nested\n");
+    assert_eq!(output(&mut shell, "$(echo echo) works"), "works\n");
+
+    // Unquoted output is split into fields, quoted output is kept whole
+    assert_eq!(output(&mut shell, "printf '[%s]' $(printf 'x  y')"), "[x][y]");
+    assert_eq!(output(&mut shell, r#"printf '[%s]' "$(printf 'x  y')""#), "[x  y]");
+    assert_eq!(
+        output(&mut shell, r#"printf '[%s]' "<$(printf 'x  y') z>""#),
+        "[<x  y z>]"
+    );
+
+    // Only trailing newlines are removed
+    assert_eq!(
+        output(&mut shell, r#"printf '[%s]' "$(printf '\n a\n\n\n')""#),
+        "[\n a]"
+    );
+
+    // An empty unquoted substitution is no field at all
+    assert_eq!(output(&mut shell, "printf '[%s]' $(true) x"), "[x]");
+    assert_eq!(output(&mut shell, r#"printf '[%s]' "$(true)" x"#), "[][x]");
+
+    assert_eq!(shell.execute(&"$(false)".tokenize().parse().unwrap()), 1);
+}
+
+#[test]
+fn variables() {
+    let mut shell = Shell::new();
+    shell.env.set("GREETING", "hello   big world");
+    // Inherited from the environment the tests run in
+    assert_eq!(
+        output(&mut shell, "printf '%s\\n' ${HOME}"),
+        format!("{}\n", std::env::var("HOME").unwrap())
+    );
+    assert_eq!(
+        output(
+            &mut shell,
+            "printf '[%s]' $GREETING \"$GREETING\" x$UNSET'$GREETING'"
+        ),
+        "[hello][big][world][hello   big world][x$GREETING]"
+    );
+
+    // Only exported variables reach child processes
+    assert_eq!(output(&mut shell, "sh -c 'echo [$GREETING]'"), "[]\n");
+    shell.execute(&"export GREETING OTHER=1".tokenize().parse().unwrap());
+    assert_eq!(
+        output(&mut shell, "sh -c 'echo $GREETING $OTHER'"),
+        "hello big world 1\n"
+    );
+    shell.execute(&"unset OTHER".tokenize().parse().unwrap());
+    assert_eq!(output(&mut shell, "echo \"[$OTHER]\""), "[]\n");
+}
+
+#[test]
+fn assignments() {
+    let mut shell = Shell::new();
+    shell.execute(&"A=1 B='two words' C=$A$(echo 2)".tokenize().parse().unwrap());
+    assert_eq!(shell.env.get("B"), Some("two words"));
+    assert_eq!(output(&mut shell, "echo $A $C"), "1 12\n");
+    assert_eq!(shell.execute(&"D=$(false)".tokenize().parse().unwrap()), 1);
+    assert_eq!(shell.env.get("D"), Some(""));
+
+    // Prefix assignments are exported to the command, and only to it
+    assert_eq!(
+        output(&mut shell, "A=3 E=4 sh -c 'echo $A $B $E'"),
+        "3 4\n"
+    );
+    assert_eq!(shell.env.get("A"), Some("1"));
+    assert_eq!(shell.env.get("E"), None);
+
+    // Anything else the command changes stays changed
+    assert_eq!(output(&mut shell, "f() { G=1; }; X=1 f; echo G=$G"), "G=1\n");
+    assert_eq!(output(&mut shell, "X=1 export Y=2; echo $Y"), "2\n");
+    assert_eq!(shell.env.get("X"), None);
+    assert_eq!(output(&mut shell, "A=5 A=6 true; echo $A"), "1\n");
+}
+
+#[test]
+fn parameter_expansion() {
+    let mut shell = Shell::new();
+    shell.env.set("FILE", "archive.tar.gz");
+    shell.env.set("EMPTY", "");
+
+    let mut expand = |word: &str| output(&mut shell, &format!("printf '%s\\n' \"{word}\""));
+    assert_eq!(expand("${#FILE}"), "14\n");
+    assert_eq!(
+        expand("${UNSET:-default} ${EMPTY-set} ${EMPTY:-null}"),
+        "default  null\n"
+    );
+    assert_eq!(expand("${FILE:+yes} ${UNSET+yes}"), "yes \n");
+    assert_eq!(
+        expand("${FILE#*.} ${FILE##*.} ${FILE%.*} ${FILE%%.*}"),
+        "tar.gz gz archive.tar archive\n"
+    );
+    assert_eq!(
+        expand("${FILE/a/A} ${FILE//a/A} ${FILE//[.]/_}"),
+        "Archive.tar.gz Archive.tAr.gz archive_tar_gz\n"
+    );
+    assert_eq!(
+        expand("${FILE:8} ${FILE:0:7} ${FILE: -2} ${FILE:1:-3}"),
+        "tar.gz archive gz rchive.tar\n"
+    );
+    // Quoted parts of a pattern match literally
+    assert_eq!(
+        expand("${FILE%'.*'} ${FILE%.'gz'}"),
+        "archive.tar.gz archive.tar\n"
+    );
+    // An operand keeps its spaces, and operators in it are just text
+    assert_eq!(
+        expand("[${UNSET:-a   b}] [${UNSET:- lead }]"),
+        "[a   b] [ lead ]\n"
+    );
+    assert_eq!(expand("${UNSET:-a;b} ${FILE/./ | }"), "a;b archive | tar.gz\n");
+
+    assert_eq!(expand("${NEW:=assigned}"), "assigned\n");
+    assert_eq!(shell.env.get("NEW"), Some("assigned"));
+    assert_eq!(
+        shell.execute(&"echo ${UNSET:?is required}".tokenize().parse().unwrap()),
+        1
+    );
+    assert_eq!(output(&mut shell, "echo ${UNSET:-a|b}"), "a|b\n");
+    // A quoted brace doesn't end the expansion
+    assert_eq!(
+        output(&mut shell, "echo ${UNSET:-\"}\"} \"${UNSET:-\"}\"}\" ${UNSET:-'{'}"),
+        "} } {\n"
+    );
+}
+
+#[test]
+fn special_parameters() {
+    let mut shell = Shell::new();
+    shell.execute(&"set -- 'a b' c".tokenize().parse().unwrap());
+    assert_eq!(
+        output(&mut shell, "echo $# $1 $2 [$3] $0"),
+        "2 a b c [] rush\n"
+    );
+    assert_eq!(
+        output(&mut shell, "printf '[%s]' $@ \"$@\" \"$*\" \"x$@y\""),
+        "[a][b][c][a b][c][a b c][xa b][cy]"
+    );
+    assert_eq!(
+        output(&mut shell, "echo ${#} ${#1} ${2:-none} ${3:-none}"),
+        "2 3 c none\n"
+    );
+
+    shell.execute(&"false".tokenize().parse().unwrap());
+    assert_eq!(output(&mut shell, "echo $?"), "1\n");
+    assert_eq!(
+        output(&mut shell, "echo $$"),
+        format!("{}\n", std::process::id())
+    );
+
+    // Without any positional parameters, "$@" is no field at all
+    shell.execute(&"set --".tokenize().parse().unwrap());
+    assert_eq!(output(&mut shell, "printf '[%s]' \"$@\" \"$*\""), "[]");
+}
+
+#[test]
+fn globbing() {
+    let dir = std::env::temp_dir().join(format!("rush-globbing-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for file in ["one.txt", "two.txt", "three.log"] {
+        fs::write(dir.join(file), "").unwrap();
+    }
+    let mut shell = Shell::new();
+    shell.env.set("DIR", dir.to_str().unwrap());
+
+    assert_eq!(
+        output(
+            &mut shell,
+            "printf '[%s]' $DIR/*.txt $DIR/t* | sed \"s|$DIR/||g\""
+        ),
+        "[one.txt][two.txt][three.log][two.txt]"
+    );
+    assert_eq!(
+        output(&mut shell, "printf '[%s]' $DIR/*.log | sed \"s|$DIR/||\""),
+        "[three.log]"
+    );
+    // Quoted or unmatched patterns are left alone
+    assert_eq!(
+        output(
+            &mut shell,
+            "printf '[%s]' \"$DIR\"/'*'.txt $DIR/*.md | sed \"s|$DIR/||g\""
+        ),
+        "[*.txt][*.md]"
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn glob_options() {
+    let dir = std::env::temp_dir().join(format!("rush-glob-options-{}", std::process::id()));
+    fs::create_dir_all(dir.join("a/b")).unwrap();
+    for file in ["a/one.rs", "a/b/two.rs", "a/.three.rs", "Four.RS"] {
+        fs::write(dir.join(file), "").unwrap();
+    }
+    let mut shell = Shell::new();
+    shell.env.set("DIR", dir.to_str().unwrap());
+    let list = |shell: &mut Shell, pattern: &str| {
+        output(
+            shell,
+            &format!("printf '[%s]' {pattern} | sed \"s|$DIR/||g\""),
+        )
+    };
+
+    assert_eq!(list(&mut shell, "$DIR/**/*.rs"), "[a/one.rs]");
+    assert_eq!(
+        shell.execute(&"shopt -s globstar dotglob nocaseglob".tokenize().parse().unwrap()),
+        0
+    );
+    assert_eq!(
+        list(&mut shell, "$DIR/**/*.rs"),
+        "[Four.RS][a/.three.rs][a/b/two.rs][a/one.rs]"
+    );
+    shell.execute(&"shopt -s extglob".tokenize().parse().unwrap());
+    assert_eq!(list(&mut shell, "$DIR/a/!(one).rs"), "[a/.three.rs]");
+
+    // What a pattern without matches becomes
+    assert_eq!(
+        list(&mut shell, "$DIR/*.md"),
+        "[$DIR/*.md]".replace("$DIR/", "")
+    );
+    shell.execute(&"shopt -s nullglob".tokenize().parse().unwrap());
+    assert_eq!(list(&mut shell, "x $DIR/*.md"), "[x]");
+    shell.execute(&"shopt -s failglob".tokenize().parse().unwrap());
+    assert_eq!(
+        shell.execute(&format!("echo {}/*.md", dir.display()).tokenize().parse().unwrap()),
+        1
+    );
+
+    assert_eq!(
+        output(&mut shell, "shopt nullglob dotglob"),
+        "nullglob\ton\ndotglob\ton\n"
+    );
+    assert_eq!(
+        shell.execute(&"shopt -u nullglob failglob".tokenize().parse().unwrap()),
+        0
+    );
+    assert_eq!(shell.execute(&"shopt nullglob".tokenize().parse().unwrap()), 1);
+    assert_eq!(shell.execute(&"shopt nosuchoption".tokenize().parse().unwrap()), 1);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn brace_expansion() {
+    let mut shell = Shell::new();
+    shell.env.set("X", "x y");
+    let mut expand = |words: &str| output(&mut shell, &format!("printf '[%s]' {words}"));
+
+    assert_eq!(expand("file.{txt,bak}"), "[file.txt][file.bak]");
+    assert_eq!(expand("a{b,c{d,e}}f"), "[abf][acdf][acef]");
+    assert_eq!(expand("{1..4} {c..a}"), "[1][2][3][4][c][b][a]");
+    assert_eq!(
+        expand("{08..10} {0..20..5} {5..1..2}"),
+        "[08][09][10][0][5][10][15][20][5][3][1]"
+    );
+    assert_eq!(expand("{a,b}{1,2}"), "[a1][a2][b1][b2]");
+    // Expansions inside braces happen afterwards, for each word
+    assert_eq!(expand("{$X,\"$X\"}"), "[x][y][x y]");
+    // Braces that don't expand are left alone
+    assert_eq!(
+        expand("{} {a} '{a,b}' {a..} x{,}"),
+        "[{}][{a}][{a,b}][{a..}][x][x]"
+    );
+}
+
+/// The home directory of `user` in the user database, which isn't `/root`
+/// for root everywhere.
+fn home_of(user: &str) -> String {
+    let name = std::ffi::CString::new(user).unwrap();
+    // SAFETY: `passwd` is plain data, for which all zeroes is valid
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    let mut buf = vec![0; 16384];
+    // SAFETY: the pointers are all valid for the call, and `buf` is as long
+    // as it says
+    let err = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    assert!(err == 0 && !found.is_null(), "no user {user}");
+    // SAFETY: `pw_dir` points into `buf`, which is still alive
+    let dir = unsafe { std::ffi::CStr::from_ptr(entry.pw_dir) };
+    dir.to_string_lossy().into_owned()
+}
+
+#[test]
+fn tilde_expansion() {
+    let mut shell = Shell::new();
+    shell.env.set("HOME", "/home/me");
+    shell.env.set("PWD", "/here");
+    shell.env.set("OLDPWD", "/there");
+    let mut expand = |words: &str| output(&mut shell, &format!("printf '[%s]' {words}"));
+
+    assert_eq!(expand("~ ~/bin ~+ ~-/x"), "[/home/me][/home/me/bin][/here][/there/x]");
+    assert_eq!(expand("~root/x"), format!("[{}/x]", home_of("root")));
+    // Only an unquoted tilde at the start of a word expands
+    assert_eq!(
+        expand("'~' \"~\" a~ ~nobody-here ~\"root\""),
+        "[~][~][a~][~nobody-here][~root]"
+    );
+
+    // Assignments also expand a tilde after each `:`
+    shell.execute(&"P=~/a:~/b:c~".tokenize().parse().unwrap());
+    assert_eq!(shell.env.get("P"), Some("/home/me/a:/home/me/b:c~"));
+    shell.execute(&"P=~+:~- Q=x~".tokenize().parse().unwrap());
+    assert_eq!(shell.env.get("P"), Some("/here:/there"));
+    assert_eq!(shell.env.get("Q"), Some("x~"));
+}
+
+#[test]
+fn lists() {
+    let mut shell = Shell::new();
+    assert_eq!(output(&mut shell, "echo a; echo b\necho c"), "a\nb\nc\n");
+    assert_eq!(
+        output(&mut shell, "true && echo and; false && echo skipped"),
+        "and\n"
+    );
+    assert_eq!(shell.status, 1);
+    assert_eq!(
+        output(&mut shell, "false || echo or; true || echo skipped"),
+        "or\n"
+    );
+    assert_eq!(shell.status, 0);
+    assert_eq!(output(&mut shell, "false && echo a || echo b"), "b\n");
+    assert_eq!(output(&mut shell, "echo $(echo a; echo b)"), "a b\n");
+
+    // Background commands don't hold up the rest of the list
+    assert_eq!(output(&mut shell, "sleep 5 & echo started"), "started\n");
+    assert_eq!(shell.status, 0);
+    let pid = shell.background_pid.expect("a background process ID");
+    assert_eq!(output(&mut shell, "echo $!"), format!("{pid}\n"));
+    run(&format!("kill {pid}"));
+}
+
+#[test]
+fn conditionals() {
+    let mut shell = Shell::new();
+    let mut check = |n: i32| {
+        let line = format!(
+            "if test {n} -eq 1; then echo one; elif test {n} -eq 2\nthen echo two\nelse echo many; fi"
+        );
+        output(&mut shell, &line)
+    };
+    assert_eq!(check(1), "one\n");
+    assert_eq!(check(2), "two\n");
+    assert_eq!(check(3), "many\n");
+
+    // The status is that of the branch taken, or 0 if there was none
+    assert_eq!(run("if true; then false; fi"), 1);
+    assert_eq!(run("if false; then true; fi"), 0);
+    assert_eq!(
+        output(&mut shell, "if false; then :; fi | tr -d x; echo $?"),
+        "0\n"
+    );
+    assert_eq!(
+        output(&mut shell, "if echo a; then echo b; fi | tr a-z A-Z"),
+        "A\nB\n"
+    );
+}
+
+#[test]
+fn loops() {
+    let mut shell = Shell::new();
+    assert_eq!(
+        output(&mut shell, "for x in a 'b c'; do echo $x; done"),
+        "a\nb c\n"
+    );
+    shell.args = vec!["p".into(), "q".into()];
+    assert_eq!(output(&mut shell, "for x; do echo $x; done"), "p\nq\n");
+    assert_eq!(
+        output(
+            &mut shell,
+            "n=3; for ((i = 0; i < $n; i++)); do echo $i; done"
+        ),
+        "0\n1\n2\n"
+    );
+    assert_eq!(
+        output(&mut shell, "x=; while test -z $x; do x=set; echo $x; done"),
+        "set\n"
+    );
+    assert_eq!(
+        output(
+            &mut shell,
+            "x=; until test -n \"$x\"; do x=set; echo $x; done"
+        ),
+        "set\n"
+    );
+    // The status is that of the last iteration, or 0 if there was none
+    assert_eq!(run("for x in a; do false; done"), 1);
+    assert_eq!(run("while false; do :; done"), 0);
+
+    assert_eq!(
+        output(
+            &mut shell,
+            "for ((i = 1; ; i++)); do if test $i -eq 2; then continue; fi; echo $i; if test $i -ge 3; then break; fi; done"
+        ),
+        "1\n3\n"
+    );
+    // `break` and `continue` can leave several loops at once
+    assert_eq!(
+        output(
+            &mut shell,
+            "for x in a b; do for y in 1 2; do test $y = 2 && continue 2; echo $x$y; done; echo no; done"
+        ),
+        "a1\nb1\n"
+    );
+    assert_eq!(
+        output(
+            &mut shell,
+            "for x in a b; do while true; do break 5; done; echo no; done; echo $x"
+        ),
+        "a\n"
+    );
+    assert_eq!(run("break"), 0);
+    assert_eq!(run("for x in a; do break 0; done"), 1);
+}
+
+#[test]
+fn arithmetic() {
+    let mut shell = Shell::new();
+    shell.env.set("N", "7");
+    let mut eval = |expr: &str| shell.evaluate(expr).unwrap();
+    assert_eq!(eval("1 + 2 * 3"), 7);
+    assert_eq!(eval("(1 + 2) * 3"), 9);
+    assert_eq!(eval("2 ** 3 ** 2"), 512);
+    assert_eq!(eval("-2 ** 2"), 4);
+    assert_eq!(eval("N % 4 == 3 && UNSET == 0"), 1);
+    assert_eq!(eval("0x10 + 010 + 1 << 2"), 100);
+    assert_eq!(eval("N > 5 ? N - 5 : 0"), 2);
+    assert_eq!(eval("x = 5, x += 2, x *= N"), 49);
+    assert_eq!(eval("x++ + ++x"), 100);
+    assert_eq!(shell.env.get("x"), Some("51"));
+    // The side of `||` that isn't needed isn't evaluated
+    assert_eq!(shell.evaluate("1 || (y = 1)").unwrap(), 1);
+    assert_eq!(shell.env.get("y"), None);
+
+    assert!(shell.evaluate("1 / 0").is_err());
+    assert!(shell.evaluate("1 +").is_err());
+    assert!(shell.evaluate("(1").is_err());
+}
+
+#[test]
+fn case_statements() {
+    let mut shell = Shell::new();
+    let mut case = |word: &str| {
+        output(
+            &mut shell,
+            &format!(
+                "case {word} in\n  start|stop) echo {word}-ing;;\n  *.txt) echo text ;&\n  '*') echo star;;\n  *) echo other\nesac"
+            ),
+        )
+    };
+    assert_eq!(case("stop"), "stop-ing\n");
+    assert_eq!(case("notes.txt"), "text\nstar\n");
+    // Quoted pattern characters match only themselves
+    assert_eq!(case("'*'"), "star\n");
+    assert_eq!(case("'a b'"), "other\n");
+
+    assert_eq!(
+        output(
+            &mut shell,
+            "case abc in a*) echo a;;& *c) echo c;;& x) echo x;; *) echo any;; esac"
+        ),
+        "a\nc\nany\n"
+    );
+    // The status is that of the body that ran, or 0 if none did
+    assert_eq!(run("case a in a) false;; esac"), 1);
+    assert_eq!(run("false; case a in b) false;; esac"), 0);
+
+    // Any command can follow a pattern, compound ones included
+    assert_eq!(
+        output(
+            &mut shell,
+            "for x in a b c d; do case $x in a) if true; then echo if; fi;; b) for i in 1; do echo for; done;; c) { echo group; };; d) case $x in d) echo case;; esac;; esac; done"
+        ),
+        "if\nfor\ngroup\ncase\n"
+    );
+}
+
+#[test]
+fn functions() {
+    let mut shell = Shell::new();
+    shell.args = vec!["outer".into()];
+    assert_eq!(
+        output(
+            &mut shell,
+            "greet() { echo hello $1 of $#; }; greet world; echo $1"
+        ),
+        "hello world of 1\nouter\n"
+    );
+    // Functions take the place of programs of the same name, in pipelines too
+    assert_eq!(
+        output(
+            &mut shell,
+            "function cat { echo not cat; }; cat /dev/null | tr a-z A-Z"
+        ),
+        "NOT CAT\n"
+    );
+
+    // `return` leaves the function from inside loops, with its own status
+    assert_eq!(
+        output(
+            &mut shell,
+            "first() { for x; do test $x -gt 2 && return $x; done; return 9; }; first 1 5 7; echo $?"
+        ),
+        "5\n"
+    );
+    assert_eq!(run("f() { false; return; }; f"), 1);
+    assert_eq!(run("return"), 1);
+
+    // Local variables are seen by the functions called, and go away on return
+    assert_eq!(
+        output(
+            &mut shell,
+            "x=global; show() { echo $x; }; f() { local x=local y; show; y=set; }; f; show; echo [$y]"
+        ),
+        "local\nglobal\n[]\n"
+    );
+    assert_eq!(run("local x=1"), 1);
+
+    // Functions can be defined anywhere a command can
+    assert_eq!(
+        output(
+            &mut shell,
+            "if true; then f() { echo F; }; fi; for i in 1; do g() { echo G; }; done; { h() { echo H; }; }; f; g; h"
+        ),
+        "F\nG\nH\n"
+    );
+}
+
+#[test]
+fn groups() {
+    let mut shell = Shell::new();
+    // A brace group runs in the shell itself, a subshell in a copy of it
+    assert_eq!(
+        output(&mut shell, "x=1; { x=2; echo $x; }; echo $x"),
+        "2\n2\n"
+    );
+    assert_eq!(
+        output(&mut shell, "x=1; (x=2; f() { true; }; echo $x); echo $x; f"),
+        "2\n1\n"
+    );
+    // Neither do the options set in a subshell
+    shell.execute(&"(shopt -s nullglob)".tokenize().parse().unwrap());
+    assert!(!shell.options.nullglob);
+    // A subshell is a process of its own, with its own working directory and
+    // the rest of the state a process has
+    assert_ne!(
+        output(&mut shell, "(sh -c 'echo $PPID')"),
+        format!("{}\n", std::process::id())
+    );
+    assert_eq!(run("(false)"), 1);
+    assert_eq!(run("{ true; }"), 0);
+
+    // Redirections and pipes apply to the group as a whole
+    assert_eq!(
+        output(&mut shell, "{ echo a; echo b 1>&2; } 2>&1 | tr a-z A-Z"),
+        "A\nB\n"
+    );
+    assert_eq!(output(&mut shell, "(echo a; echo b) | wc -l"), "2\n");
+
+    // `break` and `return` in a subshell only leave the subshell
+    assert_eq!(
+        output(&mut shell, "for i in 1 2; do (break); echo $i; done"),
+        "1\n2\n"
+    );
+    assert_eq!(
+        output(&mut shell, "f() ( return 4; echo no ); f; echo $?"),
+        "4\n"
+    );
+}
+
+#[test]
+fn escapes() {
+    let mut shell = Shell::new();
+    shell.env.set("HOME", "/home/user");
+
+    // Unquoted, a backslash quotes the character after it
+    assert_eq!(
+        output(&mut shell, r"printf '[%s]' a\ b \$HOME \~ \* \{a,b\}"),
+        "[a b][$HOME][~][*][{a,b}]"
+    );
+    assert_eq!(output(&mut shell, r"echo a\;b \# c"), "a;b # c\n");
+    assert_eq!(run(r"\true"), 0);
+    assert_eq!(run(r"\if true"), NOT_FOUND);
+    assert_eq!(
+        output(&mut shell, r"case '*' in \*) echo star;; esac"),
+        "star\n"
+    );
+
+    // In double quotes, only before `$`, `` ` ``, `"` and `\`
+    assert_eq!(
+        output(&mut shell, r#"echo "a \"quoted\" word" "\$ \\ \x""#),
+        "a \"quoted\" word $ \\ \\x\n"
+    );
+    // and never in single quotes
+    assert_eq!(output(&mut shell, r"echo 'a\b\'"), "a\\b\\\n");
+
+    // A backslash before a newline joins the lines
+    assert_eq!(output(&mut shell, "echo long \\\nline"), "long line\n");
+    assert_eq!(output(&mut shell, "ec\\\nho joined"), "joined\n");
+    assert_eq!(output(&mut shell, "echo \"a\\\nb\""), "ab\n");
+
+    // `$'...'` decodes escapes like C
+    assert_eq!(
+        output(
+            &mut shell,
+            r"printf '[%s]' $'a\tb' $'\x41\101\u263a' $'it\'s' $'\q'"
+        ),
+        "[a\tb][AA\u{263a}][it's][\\q]"
+    );
+    assert_eq!(output(&mut shell, r#"echo "$'x'""#), "$'x'\n");
+
+    // Only the body of a document whose delimiter isn't quoted is expanded
+    assert_eq!(
+        output(&mut shell, "cat <<\\EOF\n$HOME \\$\nEOF"),
+        "$HOME \\$\n"
+    );
+    assert_eq!(
+        output(&mut shell, "cat <<EOF\n$HOME \\$HOME \\a \\\njoined\nEOF"),
+        "/home/user $HOME \\a joined\n"
+    );
+}
+
+#[test]
+fn exit() {
+    let mut shell = Shell::new();
+    // `exit` stops the rest of the line and leaves with its status
+    assert_eq!(output(&mut shell, "echo a; exit 3; echo b"), "a\n");
+    assert_eq!(shell.status, 3);
+    assert_eq!(shell.flow, Some(Flow::Exit(3)));
+
+    // wherever it is called
+    assert_eq!(run("true && exit 4"), 4);
+    assert_eq!(run("f() { exit 6; }; f; true"), 6);
+    assert_eq!(run("for i in 1 2; do exit 7; done; true"), 7);
+
+    // but in a subshell or a pipeline it only leaves that
+    let mut shell = Shell::new();
+    assert_eq!(output(&mut shell, "(exit 3); echo $?"), "3\n");
+    assert_eq!(output(&mut shell, "exit 5 | cat; echo $?"), "0\n");
+    assert_eq!(shell.flow, None);
+}
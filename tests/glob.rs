@@ -0,0 +1,100 @@
+use lang::exec::Options;
+use lang::glob::{self, escape};
+
+use std::fs;
+
+fn matches(pattern: &str, text: &str) -> bool {
+    glob::matches(pattern, text, &Options::default())
+}
+
+fn expand(pattern: &str) -> Vec<String> {
+    glob::expand(pattern, &Options::default())
+}
+
+#[test]
+fn wildcards() {
+    assert!(matches("*.rs", "main.rs"));
+    assert!(matches("*.rs", ".rs"));
+    assert!(!matches("*.rs", "main.rs.bak"));
+    assert!(matches("a*b*c", "abbbc"));
+    assert!(matches("???", "abc"));
+    assert!(!matches("???", "ab"));
+}
+
+#[test]
+fn brackets() {
+    assert!(matches("[abc]", "b"));
+    assert!(!matches("[abc]", "d"));
+    assert!(matches("file[0-9].txt", "file7.txt"));
+    assert!(matches("[!a-z]", "A"));
+    assert!(!matches("[^a-z]", "q"));
+    // `]` first in the class is part of it, and an unclosed bracket is literal
+    assert!(matches("[]x]", "]"));
+    assert!(matches("[ab", "[ab"));
+}
+
+#[test]
+fn escapes() {
+    assert!(matches(r"\*", "*"));
+    assert!(!matches(r"\*", "a"));
+    assert!(matches(&escape("[*?]"), "[*?]"));
+    assert!(!matches(&escape("a*"), "abc"));
+}
+
+#[test]
+fn file_names() {
+    let dir = std::env::temp_dir().join(format!("rush-glob-{}", std::process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    for file in ["b.rs", "a.rs", ".hidden.rs", "c.txt", "sub/d.rs"] {
+        fs::write(dir.join(file), "").unwrap();
+    }
+    let dir = dir.to_str().unwrap();
+    let paths = |names: &[&str]| -> Vec<String> {
+        names.iter().map(|name| format!("{dir}/{name}")).collect()
+    };
+
+    // Sorted, and without dotfiles unless the pattern starts with a dot
+    assert_eq!(expand(&format!("{dir}/*.rs")), paths(&["a.rs", "b.rs"]));
+    assert_eq!(expand(&format!("{dir}/.*.rs")), paths(&[".hidden.rs"]));
+    assert_eq!(expand(&format!("{dir}/[!ab].*")), paths(&["c.txt"]));
+    assert_eq!(expand(&format!("{dir}/*/*.rs")), paths(&["sub/d.rs"]));
+    assert_eq!(expand(&format!("{dir}/*/")), paths(&["sub/"]));
+    assert_eq!(expand(&format!("{dir}/*.md")), Vec::<String>::new());
+
+    let options = Options {
+        globstar: true,
+        dotglob: true,
+        nocaseglob: true,
+        ..Options::default()
+    };
+    assert_eq!(
+        glob::expand(&format!("{dir}/**/*.RS"), &options),
+        paths(&[".hidden.rs", "a.rs", "b.rs", "sub/d.rs"])
+    );
+    assert_eq!(
+        glob::expand(&format!("{dir}/**"), &options),
+        paths(&["", ".hidden.rs", "a.rs", "b.rs", "c.txt", "sub", "sub/d.rs"])
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn extended() {
+    let options = Options {
+        extglob: true,
+        ..Options::default()
+    };
+    let matches = |pattern, text| glob::matches(pattern, text, &options);
+    assert!(matches("@(foo|bar).rs", "bar.rs"));
+    assert!(!matches("@(foo|bar).rs", "foobar.rs"));
+    assert!(matches("?(x)y", "y") && matches("?(x)y", "xy") && !matches("?(x)y", "xxy"));
+    assert!(matches("*(ab)c", "c") && matches("*(ab)c", "ababc"));
+    assert!(matches("+(ab|c)", "abcab") && !matches("+(ab|c)", ""));
+    assert!(matches("!(*.rs)", "main.c") && !matches("!(*.rs)", "main.rs"));
+    assert!(matches("file.!(bak)", "file.txt"));
+
+    // Only patterns when extglob is on
+    assert!(!glob::matches("@(a)", "a", &Options::default()));
+    assert!(glob::matches("@(a)", "@(a)", &Options::default()));
+}
@@ -1,8 +1,11 @@
 // use rush::lexer::*;
 use lang::lexer::{
+    tokenize_document,
+    LexError,
     Tokenize,
     Token::*
 };
+use lang::span::Position;
 
 #[test]
 fn command_arguments() {
@@ -135,6 +138,280 @@ fn pipes() {
     );
 }
 
+#[test]
+fn lists() {
+    assert_eq!(
+        "make && ./run || echo failed; sleep 1&".tokenize(),
+        vec![
+            Literal("make"),
+            Symbol("&&"),
+            Literal("./run"),
+            Symbol("||"),
+            Literal("echo"),
+            Literal("failed"),
+            Symbol(";"),
+            Literal("sleep"),
+            Literal("1"),
+            Symbol("&")
+        ]
+    );
+
+    // Newlines separate commands, comments included
+    assert_eq!(
+        "cd /tmp # go\nls|wc".tokenize(),
+        vec![
+            Literal("cd"),
+            Literal("/tmp"),
+            Comment("# go"),
+            Symbol("\n"),
+            Literal("ls"),
+            Symbol("|"),
+            Literal("wc")
+        ]
+    );
+}
+
+#[test]
+fn keywords() {
+    assert_eq!(
+        "if true; then echo if; elif false\nthen :; else echo fi; fi".tokenize(),
+        vec![
+            ControlOperator("if"),
+            Literal("true"),
+            Symbol(";"),
+            ControlOperator("then"),
+            Literal("echo"),
+            Literal("if"),
+            Symbol(";"),
+            ControlOperator("elif"),
+            Literal("false"),
+            Symbol("\n"),
+            ControlOperator("then"),
+            Literal(":"),
+            Symbol(";"),
+            ControlOperator("else"),
+            Literal("echo"),
+            Literal("fi"),
+            Symbol(";"),
+            ControlOperator("fi")
+        ]
+    );
+
+    // Only whole words are reserved
+    assert_eq!(
+        "if\"x\" fi=1".tokenize(),
+        vec![
+            Literal("if"),
+            Join,
+            Symbol("\""),
+            Str("x"),
+            Symbol("\""),
+            Literal("fi"),
+            Symbol("="),
+            Literal("1")
+        ]
+    );
+}
+
+#[test]
+fn loops() {
+    assert_eq!(
+        "while read l; do echo done; done".tokenize(),
+        vec![
+            ControlOperator("while"),
+            Literal("read"),
+            Literal("l"),
+            Symbol(";"),
+            ControlOperator("do"),
+            Literal("echo"),
+            Literal("done"),
+            Symbol(";"),
+            ControlOperator("done")
+        ]
+    );
+
+    // The expressions of a C-style loop are kept together
+    assert_eq!(
+        "for ((i = 0; i < (n); i++)); do :; done".tokenize(),
+        vec![
+            ControlOperator("for"),
+            Symbol("(("),
+            Str("i = 0; i < (n); i++"),
+            Symbol("))"),
+            Symbol(";"),
+            ControlOperator("do"),
+            Literal(":"),
+            Symbol(";"),
+            ControlOperator("done")
+        ]
+    );
+}
+
+#[test]
+fn case_statements() {
+    assert_eq!(
+        "case $1 in (a|b) x;; *.c) y;& *) z;;& esac".tokenize(),
+        vec![
+            ControlOperator("case"),
+            Symbol("$"),
+            Identifier("1"),
+            Literal("in"),
+            Symbol("("),
+            Literal("a"),
+            Symbol("|"),
+            Literal("b"),
+            Symbol(")"),
+            Literal("x"),
+            Symbol(";;"),
+            Wildcard("*.c"),
+            Symbol(")"),
+            Literal("y"),
+            Symbol(";&"),
+            Wildcard("*"),
+            Symbol(")"),
+            Literal("z"),
+            Symbol(";;&"),
+            ControlOperator("esac")
+        ]
+    );
+
+    // The `)` of a pattern doesn't end a command substitution
+    assert_eq!(
+        "$(case a in a) b;; esac)c".tokenize(),
+        vec![
+            Symbol("$"),
+            Symbol("("),
+            ControlOperator("case"),
+            Literal("a"),
+            Literal("in"),
+            Literal("a"),
+            Symbol(")"),
+            Literal("b"),
+            Symbol(";;"),
+            ControlOperator("esac"),
+            Symbol(")"),
+            Join,
+            Literal("c")
+        ]
+    );
+
+    // A compound command can follow a pattern right away
+    assert_eq!(
+        "case x in a) if b; then c; fi;; d) { e; };; esac".tokenize(),
+        vec![
+            ControlOperator("case"),
+            Literal("x"),
+            Literal("in"),
+            Literal("a"),
+            Symbol(")"),
+            ControlOperator("if"),
+            Literal("b"),
+            Symbol(";"),
+            ControlOperator("then"),
+            Literal("c"),
+            Symbol(";"),
+            ControlOperator("fi"),
+            Symbol(";;"),
+            Literal("d"),
+            Symbol(")"),
+            ControlOperator("{"),
+            Literal("e"),
+            Symbol(";"),
+            ControlOperator("}"),
+            Symbol(";;"),
+            ControlOperator("esac")
+        ]
+    );
+    assert_eq!(
+        "case x in a) for i in b; do c; done;; esac".tokenize()[5],
+        ControlOperator("for")
+    );
+    assert_eq!(
+        "case x in a) case y in b) c;; esac;; esac".tokenize()[5],
+        ControlOperator("case")
+    );
+}
+
+#[test]
+fn functions() {
+    assert_eq!(
+        "greet() { echo {; }".tokenize(),
+        vec![
+            Literal("greet"),
+            Symbol("("),
+            Symbol(")"),
+            ControlOperator("{"),
+            Literal("echo"),
+            Literal("{"),
+            Symbol(";"),
+            ControlOperator("}")
+        ]
+    );
+    assert_eq!(
+        "function greet { if :; fi }".tokenize(),
+        vec![
+            ControlOperator("function"),
+            Literal("greet"),
+            ControlOperator("{"),
+            ControlOperator("if"),
+            Literal(":"),
+            Symbol(";"),
+            ControlOperator("fi"),
+            ControlOperator("}")
+        ]
+    );
+    // A function defined right after a keyword
+    assert_eq!(
+        "then f() { :; }".tokenize(),
+        vec![
+            ControlOperator("then"),
+            Literal("f"),
+            Symbol("("),
+            Symbol(")"),
+            ControlOperator("{"),
+            Literal(":"),
+            Symbol(";"),
+            ControlOperator("}")
+        ]
+    );
+}
+
+#[test]
+fn groups() {
+    assert_eq!(
+        "{ a; } > out && (b | c)".tokenize(),
+        vec![
+            ControlOperator("{"),
+            Literal("a"),
+            Symbol(";"),
+            ControlOperator("}"),
+            Symbol(">"),
+            Literal("out"),
+            Symbol("&&"),
+            Symbol("("),
+            Literal("b"),
+            Symbol("|"),
+            Literal("c"),
+            Symbol(")")
+        ]
+    );
+    assert_eq!(
+        "f() if a; then b; fi".tokenize(),
+        vec![
+            Literal("f"),
+            Symbol("("),
+            Symbol(")"),
+            ControlOperator("if"),
+            Literal("a"),
+            Symbol(";"),
+            ControlOperator("then"),
+            Literal("b"),
+            Symbol(";"),
+            ControlOperator("fi")
+        ]
+    );
+}
+
 #[test]
 fn io_redirections() {
     assert_eq!(
@@ -172,6 +449,127 @@ fn io_redirections() {
     )
 }
 
+#[test]
+fn fd_redirections() {
+    assert_eq!(
+        "make 2> errors.log >>build.log".tokenize(),
+        vec![
+            Literal("make"),
+            Symbol("2>"),
+            Literal("errors.log"),
+            Symbol(">>"),
+            Literal("build.log")
+        ]
+    );
+    assert_eq!(
+        "cargo build 2>&1 | less".tokenize(),
+        vec![
+            Literal("cargo"),
+            Literal("build"),
+            Symbol("2>&"),
+            Literal("1"),
+            Symbol("|"),
+            Literal("less")
+        ]
+    );
+    assert_eq!(
+        "ls &>out 0<in &>> all".tokenize(),
+        vec![
+            Literal("ls"),
+            Symbol("&>"),
+            Literal("out"),
+            Symbol("0<"),
+            Literal("in"),
+            Symbol("&>>"),
+            Literal("all")
+        ]
+    );
+    // Only a bare number is a file descriptor
+    assert_eq!(
+        "echo a2>file".tokenize(),
+        vec![Literal("echo"), Literal("a2"), Symbol(">"), Literal("file")]
+    );
+}
+
+#[test]
+fn here_documents() {
+    // The body follows the delimiter, whatever else is on the line
+    assert_eq!(
+        "cat <<EOF > file\nHello $USER\n  indented\nEOF\nwc -l file".tokenize(),
+        vec![
+            Literal("cat"),
+            Symbol("<<"),
+            Literal("EOF"),
+            Str("Hello $USER\n  indented\n"),
+            Symbol(">"),
+            Literal("file"),
+            Symbol("\n"),
+            Literal("wc"),
+            Literal("-l"),
+            Literal("file")
+        ]
+    );
+
+    // `<<-` strips leading tabs, and several documents are read in order
+    assert_eq!(
+        "paste <<-A 3<< 'B'\n\t\tone\n\tA\ntwo\nB".tokenize(),
+        vec![
+            Literal("paste"),
+            Symbol("<<-"),
+            Literal("A"),
+            Str("one\n"),
+            Symbol("3<<"),
+            Symbol("\'"),
+            Str("B"),
+            Symbol("\'"),
+            Str("two\n"),
+            Symbol("\n")
+        ]
+    );
+
+    // The end of the input ends an open document
+    assert_eq!(
+        "cat <<EOF\nunfinished".tokenize(),
+        vec![
+            Literal("cat"),
+            Symbol("<<"),
+            Literal("EOF"),
+            Str("unfinished\n"),
+            Symbol("\n")
+        ]
+    );
+
+    assert_eq!(
+        "tr a-z A-Z <<< word".tokenize(),
+        vec![
+            Literal("tr"),
+            Literal("a-z"),
+            Literal("A-Z"),
+            Symbol("<<<"),
+            Literal("word")
+        ]
+    );
+}
+
+#[test]
+fn document_bodies() {
+    assert_eq!(
+        tokenize_document("\"$HOME\" is $(pwd)\n"),
+        vec![
+            Str("\""),
+            Symbol("$"),
+            Identifier("HOME"),
+            Str("\" is "),
+            Symbol("$"),
+            Symbol("("),
+            Literal("pwd"),
+            Symbol(")"),
+            Str("\n")
+        ]
+    );
+    assert_eq!(tokenize_document(""), vec![Str("")]);
+}
+
 #[test]
 fn variables() {
     assert_eq!(
@@ -190,6 +588,143 @@ fn variables() {
             Symbol("\"")
         ]
     );
+    // Single quotes don't expand anything
+    assert_eq!(
+        "echo '$VAR'".tokenize(),
+        vec![Literal("echo"), Symbol("\'"), Str("$VAR"), Symbol("\'")]
+    );
+}
+
+#[test]
+fn braces() {
+    assert_eq!(
+        "cp file.{txt,bak} {1..3} *.{rs,toml}".tokenize(),
+        vec![
+            Literal("cp"),
+            Literal("file.{txt,bak}"),
+            Literal("{1..3}"),
+            Wildcard("*.{rs,toml}")
+        ]
+    );
+}
+
+#[test]
+fn wildcards() {
+    assert_eq!(
+        "ls *.rs file?.txt [!a-z]* src/[ab]".tokenize(),
+        vec![
+            Literal("ls"),
+            Wildcard("*.rs"),
+            Wildcard("file?.txt"),
+            Wildcard("[!a-z]*"),
+            Wildcard("src/[ab]")
+        ]
+    );
+    assert_eq!(
+        "ls !(*.o) src/*.+(rs|toml) | wc".tokenize(),
+        vec![
+            Literal("ls"),
+            Wildcard("!(*.o)"),
+            Wildcard("src/*.+(rs|toml)"),
+            Symbol("|"),
+            Literal("wc")
+        ]
+    );
+    assert_eq!(
+        "echo $DIR/*".tokenize(),
+        vec![
+            Literal("echo"),
+            Symbol("$"),
+            Identifier("DIR"),
+            Join,
+            Wildcard("/*")
+        ]
+    );
+}
+
+#[test]
+fn special_parameters() {
+    assert_eq!(
+        "echo $? $$ $# $@ $12".tokenize(),
+        vec![
+            Literal("echo"),
+            Symbol("$"),
+            Identifier("?"),
+            Symbol("$"),
+            Identifier("$"),
+            Symbol("$"),
+            Identifier("#"),
+            Symbol("$"),
+            Identifier("@"),
+            Symbol("$"),
+            Identifier("1"),
+            Join,
+            Literal("2")
+        ]
+    );
+    assert_eq!(
+        r#""$*" ${10}"#.tokenize(),
+        vec![
+            Symbol("\""),
+            Str(""),
+            Symbol("$"),
+            Identifier("*"),
+            Str(""),
+            Symbol("\""),
+            Symbol("$"),
+            Identifier("{10}")
+        ]
+    );
+}
+
+#[test]
+fn joined_words() {
+    assert_eq!(
+        "ls ${HOME}/bin $A$B".tokenize(),
+        vec![
+            Literal("ls"),
+            Symbol("$"),
+            Identifier("{HOME}"),
+            Join,
+            Literal("/bin"),
+            Symbol("$"),
+            Identifier("A"),
+            Join,
+            Symbol("$"),
+            Identifier("B")
+        ]
+    );
+    assert_eq!(
+        r#"echo "a"'b'c#d #e"#.tokenize(),
+        vec![
+            Literal("echo"),
+            Symbol("\""),
+            Str("a"),
+            Symbol("\""),
+            Join,
+            Symbol("\'"),
+            Str("b"),
+            Symbol("\'"),
+            Join,
+            Literal("c#d"),
+            Comment("#e")
+        ]
+    );
+    // An assignment's value follows the `=` without a join
+    assert_eq!(
+        "env A=$HOME B= C".tokenize(),
+        vec![
+            Literal("env"),
+            Literal("A"),
+            Symbol("="),
+            Symbol("$"),
+            Identifier("HOME"),
+            Literal("B"),
+            Symbol("="),
+            Literal(""),
+            Literal("C")
+        ]
+    );
 }
 
 #[test]
@@ -298,3 +833,152 @@ fn complex() {
     //     ]
     // );
 }
+
+#[test]
+fn spans() {
+    let at = |byte, line, column| Position { byte, line, column };
+    let tokens = "if true; then\n  echo \"$x\"\nfi".tokenize();
+    let starts: Vec<_> = tokens.iter().map(|t| t.span().unwrap().start).collect();
+    assert_eq!(
+        starts,
+        vec![
+            at(0, 1, 1),
+            at(3, 1, 4),
+            at(7, 1, 8),
+            at(9, 1, 10),
+            at(13, 1, 14),
+            at(16, 2, 3),
+            at(21, 2, 8),
+            at(22, 2, 9),
+            at(22, 2, 9),
+            at(23, 2, 10),
+            at(24, 2, 11),
+            at(24, 2, 11),
+            at(25, 2, 12),
+            at(26, 3, 1),
+        ]
+    );
+    // The newline ends at the start of the next line
+    assert_eq!(tokens[4].span().unwrap().end, at(14, 2, 1));
+
+    // Leading whitespace still counts towards the columns
+    let tokens = "  \techo".tokenize();
+    assert_eq!(tokens[0].span().unwrap().start, at(3, 1, 4));
+
+    // A here-document's body starts on the line after its operator
+    let tokens = "cat <<EOF\nhi\nEOF\n".tokenize();
+    assert_eq!(tokens[3], Str("hi\n"));
+    let span = tokens[3].span().unwrap();
+    assert_eq!((span.start, span.end), (at(10, 2, 1), at(13, 3, 1)));
+}
+
+#[test]
+fn unterminated() {
+    let error = |line: &str| line.try_tokenize().unwrap_err();
+    let start = |line: &str| {
+        let at = error(line).start();
+        (at.line, at.column)
+    };
+
+    assert_eq!(error("echo \"abc"), LexError::UnterminatedQuote("\"".into()));
+    assert_eq!(error("echo 'a\"b"), LexError::UnterminatedQuote("'".into()));
+    assert_eq!(error("echo \"$x"), LexError::UnterminatedQuote("\"".into()));
+    assert_eq!(error("echo $(ls"), LexError::UnterminatedSubstitution("$(".into()));
+    assert_eq!(error("echo $(echo $x"), LexError::UnterminatedSubstitution("$(".into()));
+    assert_eq!(error("echo \"${x:-a"), LexError::UnterminatedSubstitution("${".into()));
+    assert_eq!(error("(echo a"), LexError::UnterminatedParen("(".into()));
+    assert_eq!(error("for ((i=0"), LexError::UnterminatedParen("((".into()));
+    assert_eq!(error("cat <<EOF\nhi"), LexError::UnterminatedHereDoc("EOF".into()));
+    assert_eq!(error("cat <<EOF"), LexError::UnterminatedHereDoc("EOF".into()));
+
+    // The innermost construct is the one reported
+    assert_eq!(start("echo \"$(ls"), (1, 7));
+    assert_eq!(start("echo $(\necho \"a"), (2, 6));
+
+    assert_eq!(
+        error("echo ${x").to_string(),
+        "unexpected EOF while looking for matching `}'"
+    );
+    assert_eq!(
+        error("cat <<END").to_string(),
+        "here-document delimited by end-of-file (wanted `END')"
+    );
+
+    // Complete input tokenizes the same either way
+    for line in ["echo \"a\" 'b' $(c) ${d}", "cat <<EOF\nhi\nEOF", "(a) && $b"] {
+        assert_eq!(line.try_tokenize().unwrap(), line.tokenize());
+    }
+    // A `(` that can't open anything isn't waited on, not even after a pattern
+    for line in ["echo a(b", "echo *x(b c"] {
+        assert_eq!(line.try_tokenize().unwrap(), line.tokenize());
+    }
+    // while the infallible API keeps what it can of the rest
+    assert_eq!(
+        "echo \"abc".tokenize(),
+        vec![Literal("echo"), Symbol("\""), Str("abc")]
+    );
+}
+
+#[test]
+fn escapes() {
+    // An escaped character is a part of its own after a backslash
+    assert_eq!(
+        r"echo a\ b \$x".tokenize(),
+        vec![
+            Literal("echo"),
+            Literal("a"),
+            Join,
+            Symbol("\\"),
+            Str(" "),
+            Join,
+            Literal("b"),
+            Symbol("\\"),
+            Str("$"),
+            Join,
+            Literal("x"),
+        ]
+    );
+    assert_eq!(
+        r#""a \"b\" \$ \\ \c" 'd\'"#.tokenize(),
+        vec![
+            Symbol("\""),
+            Str(r#"a "b" $ \ \c"#),
+            Symbol("\""),
+            Symbol("'"),
+            Str(r"d\"),
+            Symbol("'"),
+        ]
+    );
+
+    // Line continuations disappear, even in the middle of a word
+    assert_eq!(
+        "ec\\\nho a \\\n  b".tokenize(),
+        vec![Literal("echo"), Literal("a"), Literal("b")]
+    );
+
+    // `$'...'` is a single quoted string with its escapes decoded
+    assert_eq!(
+        r"$'a\tb\x41\101\u263a\e\q'".tokenize(),
+        vec![Symbol("'"), Str("a\tbAA\u{263a}\x1b\\q"), Symbol("'")]
+    );
+
+    // A trailing backslash stands for itself, but leaves the input
+    // incomplete for `try_tokenize`
+    assert_eq!(
+        r"echo a\".tokenize(),
+        vec![Literal("echo"), Literal("a"), Join, Symbol("\\"), Str("\\")]
+    );
+    assert_eq!(
+        "echo a\\\n".try_tokenize(),
+        Err(LexError::UnterminatedLine("\\".into()))
+    );
+    // unlike an escaped space at the end
+    assert_eq!(
+        "echo a\\ ".try_tokenize().unwrap(),
+        vec![Literal("echo"), Literal("a"), Join, Symbol("\\"), Str(" ")]
+    );
+
+    // Quoting any part of a here-document's delimiter quotes the whole
+    let tokens = "cat <<E\\OF\n$x\nEOF".tokenize();
+    assert_eq!(tokens[8], Str("$x\n"));
+}
@@ -1,9 +1,15 @@
 use lang::parser::{
     Tree::*,
-    Parse
+    CaseArm,
+    CaseEnd,
+    Parse,
+    ParseError,
+    ParamOp,
+    Redirection,
+    RedirectOp,
 };
 
-use lang::lexer::Token as Tok;
+use lang::lexer::{Token as Tok, Tokenize};
 
 // fn tmp() {
     
@@ -66,7 +72,7 @@ use lang::lexer::Token as Tok;
 fn command_arguments() {
     // Programs without arguments
     assert_eq!(
-        vec![Tok::Literal("~/bin/ansi_colors")].parse(),
+        vec![Tok::Literal("~/bin/ansi_colors")].parse().unwrap(),
         Command {
             name: Box::new(Literal("~/bin/ansi_colors")),
             args: vec![],
@@ -78,7 +84,7 @@ fn command_arguments() {
             Tok::Literal("ls"),
             Tok::Literal("-F"),
             Tok::Literal("--group-directories-first")
-        ].parse(),
+        ].parse().unwrap(),
         Command {
             name: Box::new(Literal("ls")),
             args: vec![Literal("-F"), Literal("--group-directories-first")],
@@ -90,7 +96,7 @@ fn command_arguments() {
             Tok::Literal("-selection"),
             Tok::Literal("c"),
             Tok::Literal("-o")
-        ].parse(),
+        ].parse().unwrap(),
         Command {
             name: Box::new(Literal("xclip")),
             args: vec![Literal("-selection"), Literal("c"), Literal("-o")],
@@ -107,7 +113,7 @@ fn quotes() {
             Tok::Symbol("\""),
             Tok::Str(":Zone.Identifier"),
             Tok::Symbol("\"")
-        ].parse(),
+        ].parse().unwrap(),
         Command {
             name: Box::new(Literal("grep")),
             args: vec![String(r#"":Zone.Identifier""#)],
@@ -120,7 +126,7 @@ fn quotes() {
             Tok::Symbol("\""),
             Tok::Str("My name is Cole McAnelly"),
             Tok::Symbol("\"")
-        ].parse(),
+        ].parse().unwrap(),
         Command {
             name: Box::new(Literal("echo")),
             args: vec![String(r#""My name is Cole McAnelly""#)],
@@ -128,35 +134,103 @@ fn quotes() {
     );
 
     // Single quotes with assignment operator
-    // assert_eq!(
-    //     vec![
-    //         Tok::Literal("alias"),
-    //         Tok::Literal("colors"),
-    //         Tok::Symbol("="),
-    //         Tok::Symbol("\'"),
-    //         Tok::Str("~/bin/ansi_colors"),
-    //         Tok::Symbol("\'")
-    //     ].parse(),
-    //     todo!()
-    // );
-    /*
-    Command {
-        name: Box::new(Literal("alias")),
-        args: vec![Literal("colors")],
-    }
-    */
+    assert_eq!(
+        vec![
+            Tok::Literal("alias"),
+            Tok::Literal("colors"),
+            Tok::Symbol("="),
+            Tok::Symbol("\'"),
+            Tok::Str("~/bin/ansi_colors"),
+            Tok::Symbol("\'")
+        ].parse().unwrap(),
+        Command {
+            name: Box::new(Literal("alias")),
+            args: vec![Word(vec![Literal("colors="), String("'~/bin/ansi_colors'")])],
+        }
+    );
 
     // Double quoted String with internal spaces, and assignment operator
-    // assert_eq!(
-    //     vec![
-    //         Tok::Literal("MY_VAR"),
-    //         Tok::Symbol("="),
-    //         Tok::Symbol("\""),
-    //         Tok::Str("this is the value of my variable"),
-    //         Tok::Symbol("\"")
-    //     ].parse(),
-    //     todo!()
-    // );
+    assert_eq!(
+        vec![
+            Tok::Literal("MY_VAR"),
+            Tok::Symbol("="),
+            Tok::Symbol("\""),
+            Tok::Str("this is the value of my variable"),
+            Tok::Symbol("\"")
+        ].parse().unwrap(),
+        Assign {
+            vars: vec![("MY_VAR", String(r#""this is the value of my variable""#))],
+            command: None,
+        }
+    );
+}
+
+#[test]
+fn assignments() {
+    assert_eq!(
+        vec![
+            Tok::Literal("A"),
+            Tok::Symbol("="),
+            Tok::Literal(""),
+            Tok::Literal("B"),
+            Tok::Symbol("="),
+            Tok::Literal("x"),
+            Tok::Join,
+            Tok::Symbol("$"),
+            Tok::Identifier("Y"),
+        ].parse().unwrap(),
+        Assign {
+            vars: vec![
+                ("A", Literal("")),
+                ("B", Word(vec![Literal("x"), Identifier("Y")])),
+            ],
+            command: None,
+        }
+    );
+
+    // Prefix assignments, which only apply to the command
+    assert_eq!(
+        vec![
+            Tok::Literal("FOO"),
+            Tok::Symbol("="),
+            Tok::Literal("1"),
+            Tok::Symbol(">"),
+            Tok::Literal("out"),
+            Tok::Literal("env"),
+            Tok::Literal("BAR"),
+            Tok::Symbol("="),
+            Tok::Literal("2"),
+        ].parse().unwrap(),
+        Assign {
+            vars: vec![("FOO", Literal("1"))],
+            command: Some(Box::new(Redirect {
+                tree: Box::new(Command {
+                    name: Box::new(Literal("env")),
+                    args: vec![Literal("BAR=2")],
+                }),
+                redirects: vec![Redirection {
+                    fd: 1,
+                    op: RedirectOp::Write,
+                    target: Literal("out"),
+                }],
+            })),
+        }
+    );
+
+    // Only an unquoted name makes an assignment
+    assert_eq!(
+        vec![
+            Tok::Symbol("\'"),
+            Tok::Str("A"),
+            Tok::Symbol("\'"),
+            Tok::Join,
+            Tok::Literal("=1"),
+        ].parse().unwrap(),
+        Command {
+            name: Box::new(Word(vec![String("'A'"), Literal("=1")])),
+            args: vec![],
+        }
+    );
 }
 
 #[test]
@@ -171,7 +245,7 @@ fn pipes() {
             Tok::Symbol("|"),
             Tok::Literal("xargs"),
             Tok::Literal("rm")
-        ].parse(),
+        ].parse().unwrap(),
         Pipe(
             Box::new(Pipe(
                 Box::new(Command { 
@@ -198,68 +272,642 @@ fn pipes() {
     //         Tok::Literal("basename"),
     //         Tok::Literal("-s"),
     //         Tok::Literal(".rs")
-    //     ].parse(),
+    //     ].parse().unwrap(),
     //     todo!()
     // );
 }
 
-// #[test]
-// fn io_redirections() {
-//     assert_eq!(
-//         vec![
-//             Tok::Literal("cat"),
-//             Tok::Symbol("<<"),
-//             Tok::Literal("EOF"),
-//             Tok::Symbol(">"),
-//             Tok::Literal("file"),
-//             Tok::Symbol("|"),
-//             Tok::Literal("wc"),
-//             Tok::Literal("-c"),
-//             Tok::Symbol("|"),
-//             Tok::Literal("tr"),
-//             Tok::Literal("-d"),
-//             Tok::Symbol("\""),
-//             Tok::Str(" "),
-//             Tok::Symbol("\""),
-//             Tok::Symbol(">"),
-//             Tok::Literal("file2")
-//         ].parse(),
-//         todo!()
-//     );
-
-//     assert_eq!(
-//         vec![
-//             Tok::Literal("echo"),
-//             Tok::Symbol("\""),
-//             Tok::Str("This is Cole McAnelly's file, and I am writing my name inside of it!!"),
-//             Tok::Symbol("\""),
-//             Tok::Symbol(">>"),
-//             Tok::Literal("my_file")
-//         ].parse(),
-//         todo!()
-//     )
-// }
+#[test]
+fn lists() {
+    // `&&` and `||` bind tighter than `;` and `&`, and group to the left
+    assert_eq!(
+        vec![
+            Tok::Literal("make"),
+            Tok::Symbol("&&"),
+            Tok::Literal("test"),
+            Tok::Symbol("||"),
+            Tok::Literal("fail"),
+            Tok::Symbol(";"),
+            Tok::Literal("sleep"),
+            Tok::Symbol("&"),
+            Tok::Literal("wait"),
+        ].parse().unwrap(),
+        List(vec![
+            Or(
+                Box::new(And(
+                    Box::new(Command { name: Box::new(Literal("make")), args: vec![] }),
+                    Box::new(Command { name: Box::new(Literal("test")), args: vec![] }),
+                )),
+                Box::new(Command { name: Box::new(Literal("fail")), args: vec![] }),
+            ),
+            Background(Box::new(Command { name: Box::new(Literal("sleep")), args: vec![] })),
+            Command { name: Box::new(Literal("wait")), args: vec![] },
+        ])
+    );
 
-// #[test]
-// fn variables() {
-//     assert_eq!(
-//         vec![Tok::Literal("echo"), Tok::Symbol("$"), Tok::Identifier("VAR")].parse(),
-//         todo!()
-//     );
-
-//     assert_eq!(
-//         vec![
-//             Tok::Literal("echo"),
-//             Tok::Symbol("\""),
-//             Tok::Str("this is "),
-//             Tok::Symbol("$"),
-//             Tok::Identifier("VAR"),
-//             Tok::Str(" right here"),
-//             Tok::Symbol("\"")
-//         ].parse(),
-//         todo!()
-//     );
-// }
+    // Newlines separate commands, except after `&&` or `||`, and comments
+    // are skipped
+    assert_eq!(
+        vec![
+            Tok::Symbol("\n"),
+            Tok::Literal("a"),
+            Tok::Comment("# first"),
+            Tok::Symbol("\n"),
+            Tok::Literal("b"),
+            Tok::Symbol("&&"),
+            Tok::Symbol("\n"),
+            Tok::Literal("c"),
+            Tok::Symbol(";"),
+        ].parse().unwrap(),
+        List(vec![
+            Command { name: Box::new(Literal("a")), args: vec![] },
+            And(
+                Box::new(Command { name: Box::new(Literal("b")), args: vec![] }),
+                Box::new(Command { name: Box::new(Literal("c")), args: vec![] }),
+            ),
+        ])
+    );
+
+    // A single command isn't a list
+    assert_eq!(
+        vec![Tok::Literal("ls"), Tok::Symbol(";")].parse().unwrap(),
+        Command { name: Box::new(Literal("ls")), args: vec![] }
+    );
+}
+
+#[test]
+fn conditionals() {
+    let command = |name: &'static str| Command { name: Box::new(Literal(name)), args: vec![] };
+    assert_eq!(
+        vec![
+            Tok::ControlOperator("if"),
+            Tok::Literal("a"),
+            Tok::Symbol(";"),
+            Tok::ControlOperator("then"),
+            Tok::Literal("b"),
+            Tok::Symbol(";"),
+            Tok::ControlOperator("elif"),
+            Tok::Literal("c"),
+            Tok::Symbol("\n"),
+            Tok::ControlOperator("then"),
+            Tok::Literal("d"),
+            Tok::Symbol("\n"),
+            Tok::Literal("e"),
+            Tok::Symbol(";"),
+            Tok::ControlOperator("else"),
+            Tok::Literal("f"),
+            Tok::Symbol(";"),
+            Tok::ControlOperator("fi"),
+        ].parse().unwrap(),
+        If {
+            branches: vec![
+                (command("a"), command("b")),
+                (command("c"), List(vec![command("d"), command("e")])),
+            ],
+            otherwise: Some(Box::new(command("f"))),
+        }
+    );
+
+    // A conditional is a command, so it can be redirected and piped
+    assert_eq!(
+        vec![
+            Tok::ControlOperator("if"),
+            Tok::Literal("a"),
+            Tok::Symbol(";"),
+            Tok::ControlOperator("then"),
+            Tok::Literal("b"),
+            Tok::Symbol(";"),
+            Tok::ControlOperator("fi"),
+            Tok::Symbol(">"),
+            Tok::Literal("out"),
+            Tok::Symbol("|"),
+            Tok::Literal("c"),
+        ].parse().unwrap(),
+        Pipe(
+            Box::new(Redirect {
+                tree: Box::new(If {
+                    branches: vec![(command("a"), command("b"))],
+                    otherwise: None,
+                }),
+                redirects: vec![
+                    Redirection { fd: 1, op: RedirectOp::Write, target: Literal("out") },
+                ],
+            }),
+            Box::new(command("c")),
+        )
+    );
+
+    // Each condition and branch needs a command in it
+    let error = |line: &str| line.tokenize().parse().unwrap_err();
+    for (line, token) in [
+        ("if then echo y; fi", "then"),
+        ("if true; then fi", "fi"),
+        ("if true; then a; elif then b; fi", "then"),
+        ("if true; then a; elif b; then fi", "fi"),
+        ("if true; then a; else fi", "fi"),
+    ] {
+        assert_eq!(
+            error(line),
+            ParseError::UnexpectedToken(Tok::ControlOperator(token.into())),
+            "{line:?}"
+        );
+    }
+}
+
+#[test]
+fn loops() {
+    let command = |name: &'static str| Command { name: Box::new(Literal(name)), args: vec![] };
+    assert_eq!(
+        vec![
+            Tok::ControlOperator("until"),
+            Tok::Literal("a"),
+            Tok::Symbol(";"),
+            Tok::ControlOperator("do"),
+            Tok::Literal("b"),
+            Tok::Symbol("\n"),
+            Tok::ControlOperator("done"),
+        ].parse().unwrap(),
+        While {
+            condition: Box::new(command("a")),
+            body: Box::new(command("b")),
+            until: true,
+        }
+    );
+
+    assert_eq!(
+        vec![
+            Tok::ControlOperator("for"),
+            Tok::Literal("x"),
+            Tok::Literal("in"),
+            Tok::Literal("a"),
+            Tok::Symbol("$"),
+            Tok::Identifier("B"),
+            Tok::Symbol("\n"),
+            Tok::ControlOperator("do"),
+            Tok::Literal("b"),
+            Tok::Symbol(";"),
+            Tok::ControlOperator("done"),
+        ].parse().unwrap(),
+        For {
+            name: "x",
+            words: Some(vec![Literal("a"), Identifier("B")]),
+            body: Box::new(command("b")),
+        }
+    );
+
+    // Without `in`, the loop is over the positional parameters
+    assert_eq!(
+        vec![
+            Tok::ControlOperator("for"),
+            Tok::Literal("x"),
+            Tok::Symbol(";"),
+            Tok::ControlOperator("do"),
+            Tok::Literal("b"),
+            Tok::Symbol(";"),
+            Tok::ControlOperator("done"),
+        ].parse().unwrap(),
+        For {
+            name: "x",
+            words: None,
+            body: Box::new(command("b")),
+        }
+    );
+
+    assert_eq!(
+        vec![
+            Tok::ControlOperator("for"),
+            Tok::Symbol("(("),
+            Tok::Str("i = 0; i < $n; i++"),
+            Tok::Symbol("))"),
+            Tok::ControlOperator("do"),
+            Tok::Literal("b"),
+            Tok::Symbol(";"),
+            Tok::ControlOperator("done"),
+        ].parse().unwrap(),
+        ArithFor {
+            init: Box::new(String(r#""i = 0""#)),
+            condition: Box::new(Quote('"', vec![String(" i < "), Identifier("n"), String("")])),
+            step: Box::new(String(r#"" i++""#)),
+            body: Box::new(command("b")),
+        }
+    );
+
+    // A loop needs a command in its condition and in its body
+    assert_eq!(
+        "while do done".tokenize().parse().unwrap_err(),
+        ParseError::UnexpectedToken(Tok::ControlOperator("do".into()))
+    );
+    assert_eq!(
+        "for i in a; do done".tokenize().parse().unwrap_err(),
+        ParseError::UnexpectedToken(Tok::ControlOperator("done".into()))
+    );
+}
+
+#[test]
+fn case_statements() {
+    let command = |name: &'static str| Command { name: Box::new(Literal(name)), args: vec![] };
+    assert_eq!(
+        vec![
+            Tok::ControlOperator("case"),
+            Tok::Symbol("$"),
+            Tok::Identifier("1"),
+            Tok::Symbol("\n"),
+            Tok::Literal("in"),
+            Tok::Symbol("\n"),
+            Tok::Symbol("("),
+            Tok::Literal("a"),
+            Tok::Symbol("|"),
+            Tok::Symbol("\""),
+            Tok::Str("b c"),
+            Tok::Symbol("\""),
+            Tok::Symbol(")"),
+            Tok::Literal("x"),
+            Tok::Symbol(";&"),
+            Tok::Symbol("\n"),
+            Tok::Wildcard("*"),
+            Tok::Symbol(")"),
+            Tok::Symbol(";;&"),
+            Tok::Literal("z"),
+            Tok::Symbol(")"),
+            Tok::Literal("y"),
+            Tok::Symbol("\n"),
+            Tok::ControlOperator("esac"),
+        ].parse().unwrap(),
+        Case {
+            word: Box::new(Identifier("1")),
+            arms: vec![
+                CaseArm {
+                    patterns: vec![Literal("a"), String(r#""b c""#)],
+                    body: command("x"),
+                    end: CaseEnd::FallThrough,
+                },
+                CaseArm { patterns: vec![Glob("*")], body: List(vec![]), end: CaseEnd::Continue },
+                CaseArm { patterns: vec![Literal("z")], body: command("y"), end: CaseEnd::Break },
+            ],
+        }
+    );
+}
+
+#[test]
+fn functions() {
+    let body = || Box::new(Command { name: Box::new(Literal("echo")), args: vec![Identifier("1")] });
+    assert_eq!(
+        vec![
+            Tok::Literal("greet"),
+            Tok::Symbol("("),
+            Tok::Symbol(")"),
+            Tok::Symbol("\n"),
+            Tok::ControlOperator("{"),
+            Tok::Literal("echo"),
+            Tok::Symbol("$"),
+            Tok::Identifier("1"),
+            Tok::Symbol(";"),
+            Tok::ControlOperator("}"),
+        ].parse().unwrap(),
+        FunctionDef { name: "greet", body: Box::new(Group { body: body(), subshell: false }) }
+    );
+    assert_eq!(
+        vec![
+            Tok::ControlOperator("function"),
+            Tok::Literal("greet"),
+            Tok::ControlOperator("{"),
+            Tok::Literal("echo"),
+            Tok::Symbol("$"),
+            Tok::Identifier("1"),
+            Tok::Symbol("\n"),
+            Tok::ControlOperator("}"),
+            Tok::Symbol(";"),
+            Tok::Literal("greet"),
+        ].parse().unwrap(),
+        List(vec![
+            FunctionDef { name: "greet", body: Box::new(Group { body: body(), subshell: false }) },
+            Command { name: Box::new(Literal("greet")), args: vec![] },
+        ])
+    );
+}
+
+#[test]
+fn groups() {
+    let command = |name| Command { name: Box::new(Literal(name)), args: vec![] };
+    assert_eq!(
+        vec![
+            Tok::ControlOperator("{"),
+            Tok::Literal("a"),
+            Tok::Symbol(";"),
+            Tok::Literal("b"),
+            Tok::Symbol(";"),
+            Tok::ControlOperator("}"),
+            Tok::Symbol(">"),
+            Tok::Literal("out"),
+        ].parse().unwrap(),
+        Redirect {
+            tree: Box::new(Group { body: Box::new(List(vec![command("a"), command("b")])), subshell: false }),
+            redirects: vec![Redirection { fd: 1, op: RedirectOp::Write, target: Literal("out") }],
+        }
+    );
+    assert_eq!(
+        vec![
+            Tok::Symbol("("),
+            Tok::Literal("a"),
+            Tok::Symbol(")"),
+            Tok::Symbol("|"),
+            Tok::Literal("b"),
+        ].parse().unwrap(),
+        Pipe(
+            Box::new(Group { body: Box::new(command("a")), subshell: true }),
+            Box::new(command("b")),
+        )
+    );
+    // Any compound command can be the body of a function
+    assert_eq!(
+        vec![
+            Tok::Literal("f"),
+            Tok::Symbol("("),
+            Tok::Symbol(")"),
+            Tok::Symbol("("),
+            Tok::Literal("a"),
+            Tok::Symbol(")"),
+        ].parse().unwrap(),
+        FunctionDef { name: "f", body: Box::new(Group { body: Box::new(command("a")), subshell: true }) }
+    );
+}
+
+#[test]
+fn io_redirections() {
+    assert_eq!(
+        vec![
+            Tok::Literal("cat"),
+            Tok::Symbol("<<"),
+            Tok::Literal("EOF"),
+            Tok::Symbol(">"),
+            Tok::Literal("file"),
+            Tok::Symbol("|"),
+            Tok::Literal("wc"),
+            Tok::Literal("-c"),
+            Tok::Symbol("|"),
+            Tok::Literal("tr"),
+            Tok::Literal("-d"),
+            Tok::Symbol("\""),
+            Tok::Str(" "),
+            Tok::Symbol("\""),
+            Tok::Symbol(">"),
+            Tok::Literal("file2")
+        ].parse().unwrap(),
+        Pipe(
+            Box::new(Pipe(
+                Box::new(Redirect {
+                    tree: Box::new(Command {
+                        name: Box::new(Literal("cat")),
+                        args: vec![],
+                    }),
+                    redirects: vec![
+                        // Without a body the document is empty
+                        Redirection { fd: 0, op: RedirectOp::HereDoc, target: String(r#""""#) },
+                        Redirection { fd: 1, op: RedirectOp::Write, target: Literal("file") },
+                    ],
+                }),
+                Box::new(Command {
+                    name: Box::new(Literal("wc")),
+                    args: vec![Literal("-c")],
+                }),
+            )),
+            Box::new(Redirect {
+                tree: Box::new(Command {
+                    name: Box::new(Literal("tr")),
+                    args: vec![Literal("-d"), String(r#"" ""#)],
+                }),
+                redirects: vec![
+                    Redirection { fd: 1, op: RedirectOp::Write, target: Literal("file2") },
+                ],
+            }),
+        )
+    );
+
+    assert_eq!(
+        vec![
+            Tok::Literal("echo"),
+            Tok::Symbol("\""),
+            Tok::Str("This is Cole McAnelly's file, and I am writing my name inside of it!!"),
+            Tok::Symbol("\""),
+            Tok::Symbol(">>"),
+            Tok::Literal("my_file")
+        ].parse().unwrap(),
+        Redirect {
+            tree: Box::new(Command {
+                name: Box::new(Literal("echo")),
+                args: vec![String(r#""This is Cole McAnelly's file, and I am writing my name inside of it!!""#)],
+            }),
+            redirects: vec![
+                Redirection { fd: 1, op: RedirectOp::Append, target: Literal("my_file") },
+            ],
+        }
+    );
+
+    // Redirections may come before the command and duplicate descriptors
+    assert_eq!(
+        vec![
+            Tok::Symbol("<"),
+            Tok::Literal("input"),
+            Tok::Literal("sort"),
+            Tok::Symbol("2>&"),
+            Tok::Literal("1"),
+            Tok::Symbol("&>"),
+            Tok::Literal("out"),
+        ].parse().unwrap(),
+        Redirect {
+            tree: Box::new(Command {
+                name: Box::new(Literal("sort")),
+                args: vec![],
+            }),
+            redirects: vec![
+                Redirection { fd: 0, op: RedirectOp::Read, target: Literal("input") },
+                Redirection { fd: 2, op: RedirectOp::Duplicate, target: Literal("1") },
+                Redirection { fd: 1, op: RedirectOp::WriteAll, target: Literal("out") },
+            ],
+        }
+    );
+}
+
+#[test]
+fn here_documents() {
+    // An unquoted delimiter lets the body be expanded
+    assert_eq!(
+        vec![
+            Tok::Literal("cat"),
+            Tok::Symbol("<<"),
+            Tok::Literal("EOF"),
+            Tok::Str("Hello $USER,\n\"$(date)\"\n"),
+        ].parse().unwrap(),
+        Redirect {
+            tree: Box::new(Command {
+                name: Box::new(Literal("cat")),
+                args: vec![],
+            }),
+            redirects: vec![Redirection {
+                fd: 0,
+                op: RedirectOp::HereDoc,
+                target: Quote('"', vec![
+                    String("Hello "),
+                    Identifier("USER"),
+                    String(",\n\""),
+                    Subshell(Box::new(Command {
+                        name: Box::new(Literal("date")),
+                        args: vec![],
+                    })),
+                    String("\"\n"),
+                ]),
+            }],
+        }
+    );
+
+    // A quoted one keeps it literal
+    assert_eq!(
+        vec![
+            Tok::Literal("cat"),
+            Tok::Symbol("<<-"),
+            Tok::Symbol("\'"),
+            Tok::Str("EOF"),
+            Tok::Symbol("\'"),
+            Tok::Str("Hello $USER\n"),
+            Tok::Symbol(">"),
+            Tok::Literal("greeting"),
+        ].parse().unwrap(),
+        Redirect {
+            tree: Box::new(Command {
+                name: Box::new(Literal("cat")),
+                args: vec![],
+            }),
+            redirects: vec![
+                Redirection { fd: 0, op: RedirectOp::HereDoc, target: String("'Hello $USER\n'") },
+                Redirection { fd: 1, op: RedirectOp::Write, target: Literal("greeting") },
+            ],
+        }
+    );
+
+    assert_eq!(
+        vec![
+            Tok::Literal("bc"),
+            Tok::Symbol("<<<"),
+            Tok::Symbol("\""),
+            Tok::Str("1 + 2"),
+            Tok::Symbol("\""),
+        ].parse().unwrap(),
+        Redirect {
+            tree: Box::new(Command {
+                name: Box::new(Literal("bc")),
+                args: vec![],
+            }),
+            redirects: vec![
+                Redirection { fd: 0, op: RedirectOp::HereString, target: String(r#""1 + 2""#) },
+            ],
+        }
+    );
+}
+
+#[test]
+fn variables() {
+    assert_eq!(
+        vec![Tok::Literal("echo"), Tok::Symbol("$"), Tok::Identifier("VAR")].parse().unwrap(),
+        Command {
+            name: Box::new(Literal("echo")),
+            args: vec![Identifier("VAR")],
+        }
+    );
+
+    assert_eq!(
+        vec![
+            Tok::Literal("echo"),
+            Tok::Symbol("\""),
+            Tok::Str("this is "),
+            Tok::Symbol("$"),
+            Tok::Identifier("VAR"),
+            Tok::Str(" right here"),
+            Tok::Symbol("\"")
+        ].parse().unwrap(),
+        Command {
+            name: Box::new(Literal("echo")),
+            args: vec![Quote('"', vec![
+                String("this is "),
+                Identifier("VAR"),
+                String(" right here"),
+            ])],
+        }
+    );
+
+    // Parts without spaces between them are one word
+    assert_eq!(
+        vec![
+            Tok::Symbol("$"),
+            Tok::Identifier("{HOME}"),
+            Tok::Join,
+            Tok::Literal("/bin/tool"),
+            Tok::Literal("--name"),
+            Tok::Join,
+            Tok::Literal("="),
+            Tok::Join,
+            Tok::Symbol("$"),
+            Tok::Identifier("USER")
+        ].parse().unwrap(),
+        Command {
+            name: Box::new(Word(vec![Identifier("HOME"), Literal("/bin/tool")])),
+            args: vec![Word(vec![Literal("--name="), Identifier("USER")])],
+        }
+    );
+}
+
+#[test]
+fn parameter_expansion() {
+    let parse = |id| vec![Tok::Literal("echo"), Tok::Symbol("$"), Tok::Identifier(id)].parse().unwrap();
+    let echo = |arg| Command {
+        name: Box::new(Literal("echo")),
+        args: vec![arg],
+    };
+
+    assert_eq!(
+        parse("{#PATH}"),
+        echo(Parameter { name: "PATH", op: ParamOp::Length, args: vec![] })
+    );
+    assert_eq!(
+        parse("{EDITOR:-vi -n}"),
+        echo(Parameter {
+            name: "EDITOR",
+            op: ParamOp::Default { null: true },
+            args: vec![Literal("vi -n")],
+        })
+    );
+    assert_eq!(
+        parse("{X=$Y}"),
+        echo(Parameter {
+            name: "X",
+            op: ParamOp::Assign { null: false },
+            args: vec![Identifier("Y")],
+        })
+    );
+    assert_eq!(
+        parse("{FILE%%.*}"),
+        echo(Parameter {
+            name: "FILE",
+            op: ParamOp::RemoveSuffix { longest: true },
+            args: vec![Glob(".*")],
+        })
+    );
+    assert_eq!(
+        parse("{PATH//:/' '}"),
+        echo(Parameter {
+            name: "PATH",
+            op: ParamOp::Replace { all: true },
+            args: vec![Literal(":"), String("' '")],
+        })
+    );
+    assert_eq!(
+        parse("{X: -3:2}"),
+        echo(Parameter {
+            name: "X",
+            op: ParamOp::Substring,
+            args: vec![Literal("-3"), Literal("2")],
+        })
+    );
+}
 
 #[test]
 fn parenthesis() {
@@ -273,7 +921,7 @@ fn parenthesis() {
             Tok::Literal("ls"),
             Tok::Literal("-a"),
             Tok::Symbol(")")
-        ].parse(),
+        ].parse().unwrap(),
         Command {
             name: Box::new(Literal("echo")),
             args: vec![
@@ -284,38 +932,61 @@ fn parenthesis() {
             ],
         }
     );
-    // assert_eq!(
-    //     vec![
-    //         Tok::Literal("echo"),
-    //         Tok::Literal("-e"),
-    //         Tok::Symbol("\""),
-    //         Tok::Str(r"Here are the contents of the directory: [\n"),
-    //         Tok::Symbol("$"),
-    //         Tok::Symbol("("),
-    //         Tok::Literal("ls"),
-    //         Tok::Literal("-a"),
-    //         Tok::Symbol(")"),
-    //         Tok::Str(r"\n]"),
-    //         Tok::Symbol("\""),
-    //     ].parse(),
-    //     todo!()
-    // );
+    assert_eq!(
+        vec![
+            Tok::Literal("echo"),
+            Tok::Literal("-e"),
+            Tok::Symbol("\""),
+            Tok::Str(r"Here are the contents of the directory: [\n"),
+            Tok::Symbol("$"),
+            Tok::Symbol("("),
+            Tok::Literal("ls"),
+            Tok::Literal("-a"),
+            Tok::Symbol(")"),
+            Tok::Str(r"\n]"),
+            Tok::Symbol("\""),
+        ].parse().unwrap(),
+        Command {
+            name: Box::new(Literal("echo")),
+            args: vec![
+                Literal("-e"),
+                Quote('"', vec![
+                    String(r"Here are the contents of the directory: [\n"),
+                    Subshell(Box::new(Command {
+                        name: Box::new(Literal("ls")),
+                        args: vec![Literal("-a")],
+                    })),
+                    String(r"\n]"),
+                ]),
+            ],
+        }
+    );
 
-    // assert_eq!(
-    //     vec![
-    //         Tok::Literal("echo"),
-    //         Tok::Symbol("\""),
-    //         Tok::Str(""),
-    //         Tok::Symbol("$"),
-    //         Tok::Symbol("("),
-    //         Tok::Literal("ls"),
-    //         Tok::Literal("-a"),
-    //         Tok::Symbol(")"),
-    //         Tok::Str(""),
-    //         Tok::Symbol("\""),
-    //     ].parse(),
-    //     todo!()
-    // );
+    assert_eq!(
+        vec![
+            Tok::Literal("echo"),
+            Tok::Symbol("\""),
+            Tok::Str(""),
+            Tok::Symbol("$"),
+            Tok::Symbol("("),
+            Tok::Literal("ls"),
+            Tok::Literal("-a"),
+            Tok::Symbol(")"),
+            Tok::Str(""),
+            Tok::Symbol("\""),
+        ].parse().unwrap(),
+        Command {
+            name: Box::new(Literal("echo")),
+            args: vec![Quote('"', vec![
+                String(""),
+                Subshell(Box::new(Command {
+                    name: Box::new(Literal("ls")),
+                    args: vec![Literal("-a")],
+                })),
+                String(""),
+            ])],
+        }
+    );
 }
 
 #[test]
@@ -333,7 +1004,7 @@ fn complex() {
     //         Tok::Symbol("$"),
     //         Tok::Identifier("VAR"),
     //         Tok::Comment("# This is a comment")
-    //     ].parse(),
+    //     ].parse().unwrap(),
     //     todo!()
     // );
 
@@ -351,7 +1022,7 @@ fn complex() {
             Tok::Symbol("|"),
             Tok::Literal("xargs"),
             Tok::Literal("rm")
-        ].parse(),
+        ].parse().unwrap(),
         Pipe(
             Box::new(Pipe(
                 Box::new(Command {
@@ -391,3 +1062,133 @@ fn complex() {
     //     ]
     // , )}
 }
+
+#[test]
+fn errors() {
+    assert_eq!(
+        vec![Tok::Literal("echo"), Tok::Literal("a"), Tok::Symbol(")")].parse().unwrap_err(),
+        ParseError::UnexpectedToken(Tok::Symbol(")".into()))
+    );
+    assert_eq!(
+        vec![Tok::Symbol(";"), Tok::Literal("echo")].parse().unwrap_err(),
+        ParseError::UnexpectedToken(Tok::Symbol(";".into()))
+    );
+    assert_eq!(
+        vec![
+            Tok::ControlOperator("if"),
+            Tok::Literal("true"),
+            Tok::Symbol(";"),
+            Tok::ControlOperator("then"),
+            Tok::Literal("echo"),
+        ].parse().unwrap_err(),
+        ParseError::UnexpectedEof
+    );
+    assert_eq!(
+        vec![Tok::Literal("a"), Tok::Symbol("&&")].parse().unwrap_err(),
+        ParseError::UnexpectedEof
+    );
+    assert_eq!(
+        vec![Tok::Literal("echo"), Tok::Symbol("\"")].parse().unwrap_err(),
+        ParseError::UnbalancedQuote("\"".into())
+    );
+    assert_eq!(
+        vec![Tok::Literal("echo"), Tok::Symbol("$"), Tok::Symbol("("), Tok::Literal("ls")].parse().unwrap_err(),
+        ParseError::UnbalancedParen("(".into())
+    );
+    assert_eq!(
+        vec![Tok::Symbol("("), Tok::Literal("ls")].parse().unwrap_err(),
+        ParseError::UnbalancedParen("(".into())
+    );
+    assert_eq!(
+        vec![Tok::Literal("echo"), Tok::Symbol("$"), Tok::Identifier("{1a}")].parse().unwrap_err(),
+        ParseError::InvalidSubstitution("${1a}".into())
+    );
+    assert_eq!(
+        ParseError::UnexpectedToken(Tok::Symbol("\n".into())).to_string(),
+        "syntax error near unexpected token `newline'"
+    );
+}
+
+#[test]
+fn spans() {
+    let parse = |line: &str| line.tokenize().parse();
+
+    let tree = parse("echo ${x:-$y} z").unwrap();
+    let span = tree.span().unwrap();
+    assert_eq!((span.start.byte, span.end.byte), (0, 15));
+    let Command { args, .. } = &tree else { panic!("{tree:?} isn't a command") };
+    let span = args[0].span().unwrap();
+    assert_eq!((span.start.column, span.end.column), (8, 13));
+
+    assert_eq!(
+        parse("echo a )").unwrap_err().render("echo a )"),
+        "syntax error near unexpected token `)'\n --> 1:8\n  |\n1 | echo a )\n  |        ^"
+    );
+    let line = "if true; then\n  echo ${1a}\nfi";
+    assert_eq!(
+        parse(line).unwrap_err().render(line),
+        "${1a}: bad substitution\n --> 2:8\n  |\n2 |   echo ${1a}\n  |        ^~~~~"
+    );
+    // Errors at the end of the input point just past it
+    assert_eq!(
+        parse("a &&\n").unwrap_err().render("a &&\n"),
+        "syntax error: unexpected end of file\n --> 1:5\n  |\n1 | a &&\n  |     ^"
+    );
+}
+
+#[test]
+fn incomplete() {
+    let error = |line: &str| line.tokenize().parse().unwrap_err();
+    for line in [
+        "a |",
+        "a &&",
+        "a ||\n",
+        "if true; then",
+        "if true; then echo a; else",
+        "for x in a b",
+        "while true; do",
+        "case x in a) b;;",
+        "f()",
+        "{ echo",
+        "(echo",
+    ] {
+        assert!(error(line).is_incomplete(), "{line:?} is incomplete");
+    }
+    for line in ["echo a )", "fi", "echo a >", "a | | b", "echo a(b"] {
+        assert!(!error(line).is_incomplete(), "{line:?} is invalid");
+    }
+    assert_eq!(
+        error("echo a >"),
+        ParseError::UnexpectedToken(Tok::Symbol("\n".into()))
+    );
+
+    // A pipe can go on on the next line, like `&&` and `||`
+    assert_eq!(
+        "a |\n\nb".tokenize().parse().unwrap(),
+        Pipe(
+            Box::new(Command { name: Box::new(Literal("a")), args: vec![] }),
+            Box::new(Command { name: Box::new(Literal("b")), args: vec![] }),
+        )
+    );
+}
+
+#[test]
+fn source() {
+    let source = |line: &str| line.tokenize().parse().unwrap().source();
+    for line in [
+        "echo hi > $x",
+        "echo a\"$b\"c 'd' ${e}f ${g:-h} ${#i} ${j//k/l} $(m | n)",
+        "a && b || c; d &",
+        "if a; then b; elif c; then d; else e; fi",
+        "while a; do b; done",
+        "for i in a b; do echo $i; done",
+        "case $x in a|b) c;; *) d;& esac",
+        "f() { a; }",
+        "( a; b ) 2>&1 >> out",
+        "A=1 B=$c env",
+    ] {
+        assert_eq!(source(line), line);
+    }
+    // Parts of a word that would run together keep apart
+    assert_eq!(source("echo ${a}b"), "echo ${a}b");
+}